@@ -0,0 +1,177 @@
+use std::ffi::{CString, OsStr, OsString};
+use std::io;
+use std::net::TcpListener;
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixListener;
+use std::process::Command;
+
+use libc::c_char;
+
+/// First fd of the passed sockets, as defined by sd_listen_fds
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Open the sockets of a "tcp:host:port" or "unix:/path" list
+pub fn open(listen: &[String]) -> io::Result<Vec<OwnedFd>> {
+    let mut sockets = Vec::new();
+    for address in listen.iter() {
+        let socket: OwnedFd = if let Some(address) = address.strip_prefix("tcp:") {
+            TcpListener::bind(address)?.into()
+        } else if let Some(path) = address.strip_prefix("unix:") {
+            // a socket left by a previous run would make bind fail
+            let _ = std::fs::remove_file(path);
+            UnixListener::bind(path)?.into()
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid listen address {address}, expected tcp:host:port or unix:/path"),
+            ));
+        };
+        sockets.push(socket);
+    }
+    Ok(sockets)
+}
+
+/// What the child needs to receive the sockets, prepared before forking since the child may only
+/// call async-signal-safe functions
+///
+/// The child execs the program itself: Command sets its own environment after the pre_exec
+/// closures, which would leave no way to give LISTEN_PID the pid of the child.
+pub struct Activation {
+    sockets: Vec<RawFd>,
+    /// Where the sockets are moved before being put in place, so they do not overwrite each other
+    moved: Vec<RawFd>,
+    /// Program found in PATH, like execvp does
+    program: CString,
+    /// Only owns what argvp points to
+    _argv: Vec<CString>,
+    argvp: Vec<*const c_char>,
+    /// Environment of the command with LISTEN_PID last, NUL terminated
+    env: Vec<Vec<u8>>,
+    envp: Vec<*const c_char>,
+}
+
+// the pointers only point into argv and env, which move along with them
+unsafe impl Send for Activation {}
+unsafe impl Sync for Activation {}
+
+const LISTEN_PID: &[u8] = b"LISTEN_PID=";
+
+impl Activation {
+    /// Set LISTEN_FDS on the command and prepare its exec, the command must be fully set up
+    pub fn new(sockets: &[OwnedFd], command: &mut Command) -> io::Result<Self> {
+        command
+            .env("LISTEN_FDS", sockets.len().to_string())
+            .env_remove("LISTEN_PID")
+            .env_remove("LISTEN_FDNAMES");
+        let mut vars: Vec<(OsString, OsString)> = std::env::vars_os().collect();
+        for (key, value) in command.get_envs() {
+            vars.retain(|x| x.0 != key);
+            if let Some(value) = value {
+                vars.push((key.to_owned(), value.to_owned()));
+            }
+        }
+        let path = vars.iter().find(|x| x.0 == "PATH").map(|x| x.1.clone());
+        let program = find_program(command.get_program(), path.as_deref())?;
+        let mut env: Vec<Vec<u8>> = vars.iter().map(|(key, value)| entry(key, value)).collect();
+        // room for any pid, filled in the child
+        let mut pid = LISTEN_PID.to_vec();
+        pid.resize(LISTEN_PID.len() + 21, 0);
+        env.push(pid);
+        let mut envp: Vec<*const c_char> = env.iter().map(|x| x.as_ptr() as _).collect();
+        envp.push(std::ptr::null());
+        let argv = std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .map(|x| CString::new(x.as_bytes()))
+            .collect::<Result<Vec<CString>, _>>()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mut argvp: Vec<*const c_char> = argv.iter().map(|x| x.as_ptr()).collect();
+        argvp.push(std::ptr::null());
+        Ok(Self {
+            sockets: sockets.iter().map(|x| x.as_raw_fd()).collect(),
+            moved: vec![-1; sockets.len()],
+            program,
+            _argv: argv,
+            argvp,
+            env,
+            envp,
+        })
+    }
+
+    /// Put the sockets at fd 3 and up and exec the program with LISTEN_PID set, meant to be
+    /// called last in the child between fork and exec, only returns on error
+    pub fn exec(&mut self) -> io::Error {
+        let start = LISTEN_FDS_START + self.sockets.len() as RawFd;
+        for (socket, moved) in self.sockets.iter().zip(self.moved.iter_mut()) {
+            *moved = unsafe { libc::fcntl(*socket, libc::F_DUPFD, start) };
+            if *moved < 0 {
+                return io::Error::last_os_error();
+            }
+        }
+        for (idx, moved) in self.moved.iter().enumerate() {
+            // dup2 clears FD_CLOEXEC on the new fd
+            if unsafe { libc::dup2(*moved, LISTEN_FDS_START + idx as RawFd) } < 0 {
+                return io::Error::last_os_error();
+            }
+            unsafe { libc::close(*moved) };
+        }
+        let pid = unsafe { libc::getpid() } as u64;
+        let entry = self.env.last_mut().unwrap();
+        write_number(&mut entry[LISTEN_PID.len()..], pid);
+        unsafe {
+            libc::execve(
+                self.program.as_ptr(),
+                self.argvp.as_ptr(),
+                self.envp.as_ptr(),
+            )
+        };
+        io::Error::last_os_error()
+    }
+}
+
+/// Path of the program to exec, searched in PATH when it has no slash
+fn find_program(program: &OsStr, path: Option<&OsStr>) -> io::Result<CString> {
+    let invalid = |e| io::Error::new(io::ErrorKind::InvalidInput, e);
+    if program.as_bytes().contains(&b'/') {
+        return CString::new(program.as_bytes()).map_err(invalid);
+    }
+    let path = path.unwrap_or(OsStr::new("/usr/bin:/bin"));
+    for dir in std::env::split_paths(path) {
+        let candidate = dir.join(program);
+        let Ok(metadata) = std::fs::metadata(&candidate) else {
+            continue;
+        };
+        if metadata.is_file() && metadata.permissions().mode() & 0o111 != 0 {
+            return CString::new(candidate.into_os_string().into_vec()).map_err(invalid);
+        }
+    }
+    Err(io::Error::from(io::ErrorKind::NotFound))
+}
+
+/// "KEY=VALUE\0"
+fn entry(key: &OsStr, value: &OsStr) -> Vec<u8> {
+    let mut entry = key.as_bytes().to_vec();
+    entry.push(b'=');
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(0);
+    entry
+}
+
+/// Write a number in decimal followed by a NUL, without allocating
+fn write_number(buf: &mut [u8], mut number: u64) {
+    let mut digits = [0u8; 20];
+    let mut len = 0;
+    loop {
+        digits[len] = b'0' + (number % 10) as u8;
+        len += 1;
+        number /= 10;
+        if number == 0 {
+            break;
+        }
+    }
+    for idx in 0..len {
+        buf[idx] = digits[len - 1 - idx];
+    }
+    buf[len] = 0;
+}
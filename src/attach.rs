@@ -0,0 +1,143 @@
+use std::io::Write;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+
+use crate::command::CommandError;
+use crate::program::TMProgram;
+use crate::pty;
+use crate::shell::Shell;
+use crate::target::Target;
+
+/// Ctrl-]
+const DETACH_KEY: u8 = 0x1d;
+
+/// Turn off the signal keys of the terminal while attached, so Ctrl-C and Ctrl-Z reach the
+/// program instead of taskmaster
+struct NoSignalKeys(Option<libc::termios>);
+
+impl NoSignalKeys {
+    fn new() -> Self {
+        unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(0, &mut termios) != 0 {
+                return Self(None);
+            }
+            let original = termios;
+            termios.c_lflag &= !libc::ISIG;
+            if libc::tcsetattr(0, libc::TCSANOW, &termios) != 0 {
+                return Self(None);
+            }
+            Self(Some(original))
+        }
+    }
+}
+
+impl Drop for NoSignalKeys {
+    fn drop(&mut self) {
+        if let Some(termios) = &self.0 {
+            unsafe { libc::tcsetattr(0, libc::TCSANOW, termios) };
+        }
+    }
+}
+
+/// Forward the terminal to the stdin of a single attachable instance and its output to the
+/// terminal, until the detach key is pressed or the program exits
+pub async fn attach(
+    programs: &Arc<Mutex<Vec<TMProgram>>>,
+    target: &Target,
+    shell: &mut Shell,
+) -> Result<(), CommandError> {
+    let (name, instance, display_name, stdin, attached) = {
+        let programs = &mut programs.lock().unwrap();
+        let mut matching = programs
+            .iter_mut()
+            .enumerate()
+            .filter(|(idx, program)| target.matches(*idx, program));
+        let program = match (matching.next(), matching.next()) {
+            (Some((_, x)), None) => x,
+            (None, _) if matches!(target, Target::Index(_)) => {
+                return Err(CommandError::WrongIndex)
+            }
+            (None, _) => return Err(CommandError::UnknownTarget),
+            (Some(_), Some(_)) => return Err(CommandError::AmbiguousTarget),
+        };
+        if !program.config.attachable {
+            return Err(CommandError::NotAttachable);
+        }
+        if !program.is_running() {
+            return Err(CommandError::ProgramNotLaunched);
+        }
+        // a copy, so a write blocked on the program never holds the lock launch takes
+        let stdin = program
+            .stdin
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|x| x.try_clone().ok());
+        if let (true, Some(master), Some((rows, cols))) = (
+            program.config.pty,
+            program.stdin.lock().unwrap().as_ref(),
+            pty::terminal_size(),
+        ) {
+            let _ = pty::set_size(master, rows, cols);
+        }
+        (
+            program.name.clone(),
+            program.instance,
+            program.display_name(),
+            stdin,
+            program.attached.clone(),
+        )
+    };
+    println!("attached to {display_name}, press Ctrl-] to detach");
+    let _keys = NoSignalKeys::new();
+    attached.store(true, Ordering::Relaxed);
+    // a program that stops reading blocks the writes, keep them off the runtime
+    let (input_tx, mut input_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+    tokio::task::spawn_blocking(move || {
+        let Some(mut stdin) = stdin else {
+            return;
+        };
+        while let Some(data) = input_rx.blocking_recv() {
+            if stdin.write_all(&data).and_then(|_| stdin.flush()).is_err() {
+                break;
+            }
+        }
+    });
+    let mut interval = tokio::time::interval(Duration::from_millis(500));
+    loop {
+        tokio::select! {
+            data = shell.read_input() => {
+                let Some(mut data) = data else {
+                    break;
+                };
+                if let Some(idx) = data.iter().position(|x| *x == DETACH_KEY) {
+                    // what was typed after the detach key goes to the shell
+                    shell.unread(&data[idx + 1..]);
+                    data.truncate(idx);
+                    let _ = input_tx.send(data);
+                    break;
+                }
+                let _ = input_tx.send(data);
+            }
+            _ = interval.tick() => {
+                let running = programs
+                    .lock()
+                    .unwrap()
+                    .iter_mut()
+                    .find(|x| x.name == name && x.instance == instance)
+                    .is_some_and(|x| x.is_running());
+                if !running {
+                    println!("\r\n{display_name} exited");
+                    break;
+                }
+            }
+        }
+    }
+    attached.store(false, Ordering::Relaxed);
+    println!("\r\ndetached from {display_name}");
+    Ok(())
+}
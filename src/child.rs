@@ -0,0 +1,124 @@
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Child, ExitStatus};
+
+/// Process of a program, spawned by this taskmaster or adopted from a previous one
+#[derive(Debug)]
+pub enum TMChild {
+    Spawned(Child),
+    /// Process left running by a previous taskmaster
+    Adopted {
+        pid: u32,
+        /// Start time of the process, tells it apart from a process reusing the pid
+        start_time: u64,
+        status: Option<ExitStatus>,
+    },
+}
+
+/// Status of an adopted process that exited while it was not our child, neither an exit code nor
+/// a signal since there is no way to know them
+fn unknown_status() -> ExitStatus {
+    // the wait status of a stopped process
+    ExitStatus::from_raw(0x7f)
+}
+
+impl TMChild {
+    pub fn id(&self) -> u32 {
+        match self {
+            Self::Spawned(x) => x.id(),
+            Self::Adopted { pid, .. } => *pid,
+        }
+    }
+
+    pub fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        let (pid, start_time, status) = match self {
+            Self::Spawned(x) => return x.try_wait(),
+            Self::Adopted {
+                pid,
+                start_time,
+                status,
+            } => (*pid, *start_time, status),
+        };
+        if status.is_some() {
+            return Ok(*status);
+        }
+        // after an exec in place the processes are still our children
+        let mut raw = 0;
+        match unsafe { libc::waitpid(pid as libc::pid_t, &mut raw, libc::WNOHANG) } {
+            0 => return Ok(None),
+            x if x == pid as libc::pid_t => *status = Some(ExitStatus::from_raw(raw)),
+            _ if process_start_time(pid) == Some(start_time) => return Ok(None),
+            _ => *status = Some(unknown_status()),
+        }
+        Ok(*status)
+    }
+
+    pub fn kill(&mut self) -> io::Result<()> {
+        match self {
+            Self::Spawned(x) => x.kill(),
+            Self::Adopted { pid, .. } => {
+                let pid = *pid as libc::pid_t;
+                if self.try_wait()?.is_some() {
+                    return Ok(());
+                }
+                if unsafe { libc::kill(pid, libc::SIGKILL) } != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Start time of a live process in clock ticks since boot, None once it exited
+#[cfg(target_os = "linux")]
+pub fn process_start_time(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // the command name may contain spaces, the fields start after its closing parenthesis
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    if fields.first() == Some(&"Z") {
+        return None;
+    }
+    // starttime is the 22nd field, the state the 3rd
+    fields.get(19)?.parse().ok()
+}
+
+/// Start time of a live process in secs since the epoch, None once it exited
+#[cfg(target_os = "macos")]
+pub fn process_start_time(pid: u32) -> Option<u64> {
+    use libc::{c_void, proc_bsdinfo, proc_pidinfo, PROC_PIDTBSDINFO};
+
+    let mut info: proc_bsdinfo = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<proc_bsdinfo>() as i32;
+    let res = unsafe {
+        proc_pidinfo(
+            pid as i32,
+            PROC_PIDTBSDINFO,
+            0,
+            &mut info as *mut _ as *mut c_void,
+            size,
+        )
+    };
+    if res != size || info.pbi_status == libc::SZOMB {
+        return None;
+    }
+    Some(info.pbi_start_tvsec)
+}
+
+/// How long ago a process started, from its start time
+#[cfg(target_os = "linux")]
+pub fn process_age(start_time: u64) -> Option<std::time::Duration> {
+    let uptime = std::fs::read_to_string("/proc/uptime").ok()?;
+    let uptime: f64 = uptime.split_whitespace().next()?.parse().ok()?;
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as f64;
+    std::time::Duration::try_from_secs_f64(uptime - start_time as f64 / ticks).ok()
+}
+
+/// How long ago a process started, from its start time
+#[cfg(target_os = "macos")]
+pub fn process_age(start_time: u64) -> Option<std::time::Duration> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?;
+    now.checked_sub(std::time::Duration::from_secs(start_time))
+}
@@ -3,17 +3,29 @@ use std::fmt::{Display, Formatter};
 use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 
+use libc::c_int;
+
 use crate::program_status::ProgramStatus;
+use crate::schedule::format_time;
+use crate::signal::{parse_signal, signal_name};
+use crate::supervisor::{self, describe_exit};
+use crate::target::Target;
 use crate::Ordering;
 use crate::TMProgram;
 
 #[derive(Debug)]
 pub enum CommandUser {
     List,
-    Kill(u32),
-    Restart(u32),
-    Launch(u32),
-    Status(u32),
+    Kill(Target),
+    Restart(Target),
+    /// Restart the instances one at a time, waiting for each one to be healthy
+    RollingRestart(Target),
+    Launch(Target),
+    Status(Target),
+    Signal(Target, c_int),
+    /// Forward the terminal to the program, run by main since it needs the terminal
+    Attach(Target),
+    ReopenLogs,
     Help,
     Exit,
 }
@@ -24,6 +36,13 @@ pub enum CommandError {
     WrongIndex,
     UnknownCommand,
     MissingParams,
+    UnknownSignal,
+    UnknownTarget,
+    /// The target is already being restarted by a rolling restart
+    RolloutInProgress,
+    /// The command needs a single instance but the target matches several
+    AmbiguousTarget,
+    NotAttachable,
     RuntimeError,
 }
 
@@ -35,23 +54,34 @@ impl Display for CommandError {
 
 impl Error for CommandError {}
 
-impl TryFrom<(&str, Option<u32>)> for CommandUser {
+/// Name of an instance a command ran on, with how it went
+pub type TargetResult = (String, Result<(), CommandError>);
+
+impl TryFrom<&[&str]> for CommandUser {
     type Error = CommandError;
-    fn try_from(value: (&str, Option<u32>)) -> Result<Self, Self::Error> {
-        match value.0 {
-            "list" => Ok(CommandUser::List),
-            "exit" => Ok(CommandUser::Exit),
-            "help" => Ok(CommandUser::Help),
-            _ => match value.1 {
-                Some(idx) => match value.0 {
-                    "kill" => Ok(CommandUser::Kill(idx)),
-                    "restart" => Ok(CommandUser::Restart(idx)),
-                    "launch" => Ok(CommandUser::Launch(idx)),
-                    "status" => Ok(CommandUser::Status(idx)),
-                    _ => Err(CommandError::UnknownCommand),
-                },
-                None => Err(CommandError::MissingParams),
+    fn try_from(value: &[&str]) -> Result<Self, Self::Error> {
+        match value {
+            ["list"] => Ok(CommandUser::List),
+            ["exit"] => Ok(CommandUser::Exit),
+            ["help"] => Ok(CommandUser::Help),
+            ["reopen-logs"] => Ok(CommandUser::ReopenLogs),
+            ["signal", target, signal] => match parse_signal(signal) {
+                Some(x) => Ok(CommandUser::Signal(Target::from(*target), x)),
+                None => Err(CommandError::UnknownSignal),
             },
+            ["signal", ..] => Err(CommandError::MissingParams),
+            ["kill", target] => Ok(CommandUser::Kill(Target::from(*target))),
+            ["restart", "--rolling", target] => {
+                Ok(CommandUser::RollingRestart(Target::from(*target)))
+            }
+            ["restart", target] => Ok(CommandUser::Restart(Target::from(*target))),
+            ["launch", target] => Ok(CommandUser::Launch(Target::from(*target))),
+            ["status", target] => Ok(CommandUser::Status(Target::from(*target))),
+            ["attach" | "fg", target] => Ok(CommandUser::Attach(Target::from(*target))),
+            ["kill" | "restart" | "launch" | "status" | "attach" | "fg"] => {
+                Err(CommandError::MissingParams)
+            }
+            _ => Err(CommandError::UnknownCommand),
         }
     }
 }
@@ -60,13 +90,65 @@ impl CommandUser {
         match program.status() {
             Err(_) => return Err(CommandError::RuntimeError),
             Ok(x) => {
-                print!("{} => ", &program.config.command);
+                print!("{} => ", program.display_name());
                 match x {
                     ProgramStatus::Signal(signal) => println!("exited with code: {}", signal),
+                    ProgramStatus::Code(code) if program.completed => {
+                        println!("COMPLETED (exit code: {})", code)
+                    }
                     ProgramStatus::Code(code) => println!("exited with code: {}", code),
                     ProgramStatus::Running(state) => println!("{:?}", state),
-                    ProgramStatus::Nothing => return Err(CommandError::ProgramNotLaunched),
+                    ProgramStatus::Backoff(delay) => println!(
+                        "BACKOFF, restart {}/{} in {:.1}s",
+                        program.restarts,
+                        program.config.number_restart,
+                        delay.as_secs_f64()
+                    ),
+                    ProgramStatus::Nothing if program.waiting_dependencies => {
+                        println!("waiting for dependencies")
+                    }
+                    ProgramStatus::Nothing if program.stopped_by_user => {
+                        println!("stopped by user")
+                    }
+                    ProgramStatus::Nothing => println!("not launched"),
                 };
+                if program.fatal {
+                    println!("    gave up after {} restarts", program.restarts);
+                }
+                if program.config.readiness.is_some() {
+                    println!(
+                        "    readiness: {}",
+                        match program.readiness.success {
+                            true => "ready".to_string(),
+                            false => format!("not ready ({} failures)", program.readiness.failures),
+                        }
+                    );
+                }
+                if let Some(schedule) = &program.config.schedule {
+                    println!("    schedule: {schedule}");
+                    let scheduled = &program.scheduled;
+                    if let Some(last_run) = scheduled.last_run {
+                        println!(
+                            "    last run: {}, {}",
+                            format_time(last_run),
+                            match (scheduled.last_status, scheduled.last_duration) {
+                                (Some(status), Some(duration)) => format!(
+                                    "{} after {:.1}s",
+                                    describe_exit(status),
+                                    duration.as_secs_f64()
+                                ),
+                                _ => "running".to_string(),
+                            }
+                        );
+                    }
+                    println!(
+                        "    next run: {}",
+                        scheduled.next_run.map_or("never".to_string(), format_time)
+                    );
+                }
+                if !program.config.rlimits.is_empty() {
+                    println!("    limits: {}", program.config.rlimits);
+                }
             }
         };
         Ok(())
@@ -77,58 +159,137 @@ impl CommandUser {
             if let Err(e) = CommandUser::display_status(program) {
                 eprintln!(
                     "fetching status for [{}] raised error{e:?}",
-                    program.config.command
+                    program.display_name()
                 )
             }
         }
         Ok(())
     }
 
-    fn status_child(programs: &mut [TMProgram], idx: u32) -> Result<(), CommandError> {
-        match programs.get_mut(idx as usize) {
-            None => Err(CommandError::WrongIndex),
-            Some(x) => Self::display_status(x),
+    /// Run an action on every instance matching the target and return the result of each one
+    fn apply_to_target(
+        programs: &mut [TMProgram],
+        target: &Target,
+        mut action: impl FnMut(&mut TMProgram) -> Result<(), CommandError>,
+    ) -> Result<Vec<TargetResult>, CommandError> {
+        let results: Vec<TargetResult> = programs
+            .iter_mut()
+            .enumerate()
+            .filter(|(idx, program)| target.matches(*idx, program))
+            .map(|(_, program)| (program.display_name(), action(program)))
+            .collect();
+        match (results.is_empty(), target) {
+            (true, Target::Index(_)) => Err(CommandError::WrongIndex),
+            (true, _) => Err(CommandError::UnknownTarget),
+            (false, _) => Ok(results),
         }
     }
 
-    fn kill_child(programs: &mut [TMProgram], idx: u32) -> Result<(), CommandError> {
-        match programs.get_mut(idx as usize) {
-            None => return Err(CommandError::WrongIndex),
-            Some(x) => {
-                match &mut x.child {
-                    None => return Err(CommandError::ProgramNotLaunched),
-                    Some(y) => y.kill().unwrap(),
-                }
-                x.child = None;
+    /// Run an action on every instance matching the target, errors are returned as is when the
+    /// target is a single instance and reported per instance otherwise
+    fn for_each_target(
+        programs: &mut [TMProgram],
+        target: &Target,
+        action: impl Fn(&mut TMProgram) -> Result<(), CommandError>,
+    ) -> Result<(), CommandError> {
+        let mut results = Self::apply_to_target(programs, target, action)?;
+        if results.len() == 1 {
+            return results.remove(0).1;
+        }
+        for (name, result) in results {
+            if let Err(e) = result {
+                eprintln!("[{name}] {e}");
             }
         }
         Ok(())
     }
 
-    fn launch_child(programs: &mut [TMProgram], idx: u32) -> Result<(), CommandError> {
-        match programs.get_mut(idx as usize) {
-            None => return Err(CommandError::WrongIndex),
-            Some(program) => match program.child {
-                Some(_) => eprintln!("program already launched"),
-                None => {
-                    if let Err(_) = program.launch() {
-                        eprintln!("failed to launch program");
-                        return Err(CommandError::RuntimeError);
-                    }
-                }
-            },
-        };
+    fn kill_child(program: &mut TMProgram) -> Result<(), CommandError> {
+        program.stopped_by_user = true;
+        program.waiting_dependencies = false;
+        program.backoff_until = None;
+        program.restart_after_stop = false;
+        if !program.is_running() {
+            return Err(CommandError::ProgramNotLaunched);
+        }
+        Self::stop_child(program)
+    }
+
+    /// Send the stopsignal, the supervisor reaps the program once it exited and kills it if it
+    /// is still running after graceful_period
+    fn stop_child(program: &mut TMProgram) -> Result<(), CommandError> {
+        if program.stop_deadline.is_some() {
+            return Ok(());
+        }
+        if let Err(e) = program.stop() {
+            eprintln!("failed to stop program: {e}");
+            return Err(CommandError::RuntimeError);
+        }
         Ok(())
     }
 
-    fn restart_child(programs: &mut [TMProgram], idx: u32) -> Result<(), CommandError> {
-        if let Err(x) = Self::kill_child(programs, idx) {
-            match x {
-                CommandError::ProgramNotLaunched => {}
-                _ => return Err(x),
+    fn launch_child(program: &mut TMProgram) -> Result<(), CommandError> {
+        program.stopped_by_user = false;
+        program.restarts = 0;
+        if program.is_running() {
+            eprintln!("program already launched");
+        } else if program.launch().is_err() {
+            eprintln!("failed to launch program");
+            return Err(CommandError::RuntimeError);
+        }
+        Ok(())
+    }
+
+    /// Stop the program, the supervisor launches it again once it exited
+    fn restart_child(program: &mut TMProgram) -> Result<(), CommandError> {
+        program.waiting_dependencies = false;
+        program.backoff_until = None;
+        if !program.is_running() {
+            return Self::launch_child(program);
+        }
+        program.stopped_by_user = false;
+        program.restarts = 0;
+        program.restart_after_stop = true;
+        let result = Self::stop_child(program);
+        if result.is_err() {
+            program.restart_after_stop = false;
+        }
+        result
+    }
+
+    /// Hand the instances matching the target to the supervisor, which restarts them one at a time
+    fn rolling_restart(
+        programs: &mut [TMProgram],
+        target: &Target,
+    ) -> Result<Vec<TargetResult>, CommandError> {
+        let mut instances = Vec::new();
+        let results = Self::apply_to_target(programs, target, |x| {
+            instances.push((x.name.clone(), x.instance));
+            Ok(())
+        })?;
+        match supervisor::start_rollout(instances) {
+            true => Ok(results),
+            false => Err(CommandError::RolloutInProgress),
+        }
+    }
+
+    fn signal_child(program: &mut TMProgram, signal: c_int) -> Result<(), CommandError> {
+        if program.child.is_none() {
+            return Err(CommandError::ProgramNotLaunched);
+        }
+        if let Err(e) = program.signal(signal) {
+            eprintln!("[{}] failed to send signal: {e}", program.display_name());
+            return Err(CommandError::RuntimeError);
+        }
+        Ok(())
+    }
+
+    fn reopen_logs(programs: &mut [TMProgram]) -> Result<(), CommandError> {
+        for program in programs.iter() {
+            if let Err(e) = program.reopen_logs() {
+                eprintln!("[{}] failed to reopen logs: {e}", program.display_name());
             }
         }
-        Self::launch_child(programs, idx)?;
         Ok(())
     }
 
@@ -139,12 +300,16 @@ impl CommandUser {
                 "exit",
                 "help",
                 "list",
-                "kill [ID]",
-                "launch [ID]",
-                "restart [ID]",
-                "status [ID]"
+                "reopen-logs",
+                "kill [TARGET]",
+                "launch [TARGET]",
+                "restart [--rolling] [TARGET]",
+                "status [TARGET]",
+                "signal [TARGET] [SIGNAL]",
+                "attach|fg [TARGET]"
             ]
         );
+        println!("TARGET: ID | NAME | NAME:INSTANCE | group:GROUP | all");
         Ok(())
     }
 
@@ -159,11 +324,38 @@ impl CommandUser {
                 Ok(())
             }
             Self::List => Self::list_childs(programs),
-            Self::Status(idx) => Self::status_child(programs, *idx),
-            Self::Kill(idx) => Self::kill_child(programs, *idx),
-            Self::Launch(idx) => Self::launch_child(programs, *idx),
-            Self::Restart(idx) => Self::restart_child(programs, *idx),
+            Self::Status(target) => Self::for_each_target(programs, target, Self::display_status),
+            Self::Kill(target) => Self::for_each_target(programs, target, Self::kill_child),
+            Self::Launch(target) => Self::for_each_target(programs, target, Self::launch_child),
+            Self::Restart(target) => Self::for_each_target(programs, target, Self::restart_child),
+            Self::RollingRestart(target) => {
+                let results = Self::rolling_restart(programs, target)?;
+                println!("rolling restart of {} instances started", results.len());
+                Ok(())
+            }
+            Self::Signal(target, signal) => Self::for_each_target(programs, target, |x| {
+                Self::signal_child(x, *signal)?;
+                println!("[{}] {} sent", x.display_name(), signal_name(*signal));
+                Ok(())
+            }),
+            Self::Attach(_) => Err(CommandError::UnknownCommand),
+            Self::ReopenLogs => Self::reopen_logs(programs),
             Self::Help => Self::display_help(),
         }
     }
+
+    /// Run a command acting on a target without printing anything, and return the result for
+    /// each matching instance
+    pub fn apply(&self, programs: &mut [TMProgram]) -> Result<Vec<TargetResult>, CommandError> {
+        match self {
+            Self::Kill(target) => Self::apply_to_target(programs, target, Self::kill_child),
+            Self::Launch(target) => Self::apply_to_target(programs, target, Self::launch_child),
+            Self::Restart(target) => Self::apply_to_target(programs, target, Self::restart_child),
+            Self::RollingRestart(target) => Self::rolling_restart(programs, target),
+            Self::Signal(target, signal) => {
+                Self::apply_to_target(programs, target, |x| Self::signal_child(x, *signal))
+            }
+            _ => Err(CommandError::UnknownCommand),
+        }
+    }
 }
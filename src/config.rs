@@ -1,42 +1,519 @@
 use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::os::fd::OwnedFd;
+use std::path::Path;
+use std::sync::Arc;
 
 use libc::c_int;
 use serde::Deserialize;
 
+use crate::activation;
+use crate::event::EventKind;
 use crate::program::TMProgram;
+use crate::schedule::Schedule;
+use crate::state::{self, SavedProgram};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// A config file, or an include pattern, that could not be read
+    Read(String, std::io::Error),
+    Parse(String, toml::de::Error),
+    /// A program defined in two files, with both files
+    DuplicateProgram(String, String, String),
+    /// A program depends on a program that does not exist
+    UnknownDependency(String, String),
+    /// Programs depending on each other, in dependency order
+    DependencyCycle(Vec<String>),
+    /// A group contains a program that does not exist
+    UnknownGroupMember(String, String),
+    /// A program with options that cannot be used together
+    InvalidProgram(String, &'static str),
+    Launch(String, std::io::Error),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read(path, e) => write!(f, "failed to read config {path}: {e}"),
+            Self::Parse(path, e) => write!(f, "failed to parse config {path}: {e}"),
+            Self::DuplicateProgram(program, first, second) => {
+                write!(
+                    f,
+                    "program {program} is defined in both {first} and {second}"
+                )
+            }
+            Self::UnknownDependency(program, dependency) => {
+                write!(f, "{program} depends on unknown program {dependency}")
+            }
+            Self::DependencyCycle(cycle) => {
+                write!(f, "dependency cycle: {}", cycle.join(" -> "))
+            }
+            Self::UnknownGroupMember(group, program) => {
+                write!(f, "group {group} contains unknown program {program}")
+            }
+            Self::InvalidProgram(program, reason) => {
+                write!(f, "invalid program {program}: {reason}")
+            }
+            Self::Launch(program, e) => write!(f, "failed to launch {program}: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct TMConfig {
     #[serde(rename = "global")]
     pub global: TMGlobalConfig,
-    #[serde(rename = "programs")]
+    #[serde(rename = "programs", default)]
     pub programs: HashMap<String, TMProgramConfig>,
+    #[serde(default)]
+    pub groups: HashMap<String, TMGroupConfig>,
+    #[serde(default)]
+    pub hooks: Vec<TMHook>,
+}
+
+/// File matched by an include pattern, only defines programs
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct TMIncludedConfig {
+    #[serde(default)]
+    programs: HashMap<String, TMProgramConfig>,
+}
+
+/// Action run when an event happens
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TMHook {
+    /// Events the hook runs for
+    /// Default: every event
+    #[serde(default)]
+    pub events: Vec<EventKind>,
+    #[serde(flatten)]
+    pub action: HookAction,
+    /// Max number of runs per event kind in rate_period, the next ones are dropped
+    /// Default: 10
+    #[serde(default = "default_hook_rate_limit")]
+    pub rate_limit: u32,
+    /// in secs.
+    /// Default: 60
+    #[serde(default = "default_hook_rate_period")]
+    pub rate_period: u32,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HookAction {
+    /// Run a command with TASKMASTER_* env vars describing the event and the event as JSON on
+    /// its stdin
+    Command(Vec<String>),
+    /// POST the event as JSON to an "http://host:port/path" url
+    Url(String),
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TMGroupConfig {
+    /// Programs that belong to the group
+    pub programs: Vec<String>,
 }
 
 impl TMConfig {
-    pub fn launch_all(&self) -> Result<Vec<TMProgram>, std::io::Error> {
+    /// Read, parse and validate a config file
+    pub fn load(path: &str) -> Result<Self, ConfigError> {
+        let content =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_owned(), e))?;
+        let mut config = toml::from_str::<TMConfig>(&content)
+            .map_err(|e| ConfigError::Parse(path.to_owned(), e))?;
+        config.include(path)?;
+        for (name, program) in config.programs.iter_mut() {
+            program.sinks.get_or_insert(config.global.sinks.clone());
+            program
+                .check()
+                .map_err(|e| ConfigError::InvalidProgram(name.clone(), e))?;
+        }
+        for (name, group) in config.groups.iter() {
+            if let Some(x) = group
+                .programs
+                .iter()
+                .find(|x| !config.programs.contains_key(*x))
+            {
+                return Err(ConfigError::UnknownGroupMember(name.clone(), x.clone()));
+            }
+        }
+        config.start_order()?;
+        Ok(config)
+    }
+
+    /// Add the programs of the files matched by the include patterns, in the order of the
+    /// patterns then of the file names
+    fn include(&mut self, path: &str) -> Result<(), ConfigError> {
+        let mut origins: HashMap<String, String> = self
+            .programs
+            .keys()
+            .map(|x| (x.clone(), path.to_owned()))
+            .collect();
+        // relative patterns are relative to the directory of the config file
+        let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+        let itself = std::fs::canonicalize(path).ok();
+        for pattern in self.global.include.iter() {
+            let pattern = dir.join(pattern).to_string_lossy().into_owned();
+            let files = glob(&pattern).map_err(|e| ConfigError::Read(pattern.clone(), e))?;
+            for file in files {
+                // a pattern such as "*.toml" next to the config file also matches it
+                if std::fs::canonicalize(&file).ok() == itself {
+                    continue;
+                }
+                let content = std::fs::read_to_string(&file)
+                    .map_err(|e| ConfigError::Read(file.clone(), e))?;
+                let included = toml::from_str::<TMIncludedConfig>(&content)
+                    .map_err(|e| ConfigError::Parse(file.clone(), e))?;
+                for (name, program) in included.programs {
+                    if let Some(x) = origins.get(&name) {
+                        return Err(ConfigError::DuplicateProgram(name, x.clone(), file));
+                    }
+                    origins.insert(name.clone(), file.clone());
+                    self.programs.insert(name, program);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Names of the programs in the order they must be started: dependencies first, then by
+    /// priority and name
+    pub fn start_order(&self) -> Result<Vec<String>, ConfigError> {
+        for (name, config) in self.programs.iter() {
+            if let Some(x) = config
+                .depends_on
+                .iter()
+                .find(|x| !self.programs.contains_key(*x))
+            {
+                return Err(ConfigError::UnknownDependency(name.clone(), x.clone()));
+            }
+        }
+        let mut order: Vec<String> = Vec::new();
+        let mut remaining: Vec<&String> = self.programs.keys().collect();
+        remaining.sort_by_key(|x| (self.programs[*x].priority, *x));
+        while !remaining.is_empty() {
+            let ready = remaining.iter().position(|x| {
+                self.programs[*x]
+                    .depends_on
+                    .iter()
+                    .all(|dep| order.contains(dep))
+            });
+            match ready {
+                Some(idx) => order.push(remaining.remove(idx).clone()),
+                None => return Err(ConfigError::DependencyCycle(self.find_cycle(&remaining))),
+            }
+        }
+        Ok(order)
+    }
+
+    /// Follow the dependencies among programs that could not be ordered until one comes back
+    fn find_cycle(&self, remaining: &[&String]) -> Vec<String> {
+        let mut path: Vec<String> = vec![remaining[0].clone()];
+        loop {
+            let last = &self.programs[path.last().unwrap()];
+            let next = last
+                .depends_on
+                .iter()
+                .find(|x| remaining.contains(x))
+                .unwrap()
+                .clone();
+            if let Some(start) = path.iter().position(|x| *x == next) {
+                let mut cycle = path.split_off(start);
+                cycle.push(next);
+                return cycle;
+            }
+            path.push(next);
+        }
+    }
+
+    /// Names of the groups a program belongs to
+    pub fn groups_of(&self, program: &str) -> Vec<String> {
+        self.groups
+            .iter()
+            .filter(|(_, group)| group.programs.iter().any(|x| x == program))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Create every program instance in start order, adopt the processes saved by the previous
+    /// taskmaster, once the ones that cannot be adopted exited, and launch the autostart ones that have no dependency, the supervisor launches
+    /// the others once their dependencies are healthy
+    pub fn launch_all(&self, saved: &[SavedProgram]) -> Result<Vec<TMProgram>, ConfigError> {
+        state::stop_unadoptable(saved, self);
+        let mut res = self.create_missing(&[])?;
+        for prog in res.iter_mut() {
+            let saved = saved
+                .iter()
+                .find(|x| x.name == prog.name && x.instance == prog.instance);
+            if saved.is_some_and(|x| prog.adopt(x)) || prog.stopped_by_user {
+                continue;
+            }
+            prog.autostart()
+                .map_err(|e| ConfigError::Launch(prog.name.clone(), e))?;
+        }
+        Ok(res)
+    }
+
+    /// Create, in start order and without launching them, the instances of the config that are
+    /// not among the programs, either new programs or new instances of a program, a removed
+    /// instance still stopping is reused instead
+    pub fn create_missing(&self, programs: &[TMProgram]) -> Result<Vec<TMProgram>, ConfigError> {
         let mut res: Vec<TMProgram> = Vec::new();
-        for config in self.programs.values() {
-            let mut prog = TMProgram {
-                config: config.clone(),
-                child: None,
-            };
-            if !prog.config.autostart {
+        for name in self.start_order()? {
+            let config = &self.programs[&name];
+            let existing: Vec<u32> = programs
+                .iter()
+                .filter(|x| x.name == name)
+                .map(|x| x.instance)
+                .collect();
+            let missing: Vec<u32> = (0..config.process)
+                .filter(|x| !existing.contains(x))
+                .collect();
+            if missing.is_empty() {
                 continue;
             }
-            match prog.launch() {
-                Ok(_) => res.push(prog),
-                Err(e) => return Err(e),
+            // every instance shares the same sockets
+            let sockets = match programs.iter().find(|x| x.name == name) {
+                Some(x) => x.sockets.clone(),
+                None => Arc::new(
+                    activation::open(&config.listen)
+                        .map_err(|e| ConfigError::Launch(name.clone(), e))?,
+                ),
             };
+            for instance in missing {
+                let mut prog = TMProgram::new(name.clone(), instance, config.clone());
+                prog.groups = self.groups_of(&name);
+                prog.sockets = sockets.clone();
+                res.push(prog);
+            }
+        }
+        Ok(res)
+    }
+
+    /// Sockets of the programs whose listen changed, by program, an address still listened on
+    /// keeps its socket so it stays open
+    pub fn changed_sockets(
+        &self,
+        programs: &[TMProgram],
+    ) -> Result<HashMap<String, Arc<Vec<OwnedFd>>>, ConfigError> {
+        let mut res = HashMap::new();
+        for (name, config) in self.programs.iter() {
+            let Some(program) = programs.iter().find(|x| x.name == *name) else {
+                continue;
+            };
+            if program.config.listen == config.listen {
+                continue;
+            }
+            let mut sockets = Vec::new();
+            for address in config.listen.iter() {
+                let socket = match program.config.listen.iter().position(|x| x == address) {
+                    Some(idx) => program.sockets[idx].try_clone(),
+                    None => {
+                        activation::open(std::slice::from_ref(address)).map(|mut x| x.remove(0))
+                    }
+                };
+                sockets.push(socket.map_err(|e| ConfigError::Launch(name.clone(), e))?);
+            }
+            res.insert(name.clone(), Arc::new(sockets));
         }
         Ok(res)
     }
 }
 
+/// Paths matching a glob pattern, sorted, none when nothing matches
+fn glob(pattern: &str) -> io::Result<Vec<String>> {
+    let pattern =
+        CString::new(pattern).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut glob: libc::glob_t = unsafe { std::mem::zeroed() };
+    let res = unsafe { libc::glob(pattern.as_ptr(), 0, None, &mut glob) };
+    let paths = match res {
+        0 => (0..glob.gl_pathc)
+            .map(|idx| unsafe { CStr::from_ptr(*glob.gl_pathv.add(idx)) })
+            .map(|x| x.to_string_lossy().into_owned())
+            .collect(),
+        libc::GLOB_NOMATCH => Vec::new(),
+        _ => {
+            unsafe { libc::globfree(&mut glob) };
+            return Err(io::Error::other("glob failed"));
+        }
+    };
+    unsafe { libc::globfree(&mut glob) };
+    Ok(paths)
+}
+
 #[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct TMGlobalConfig {
     ///path were the log will be written
     pub logfile: String,
+    /// Where taskmaster events and, unless a program sets its own, program output are sent
+    /// Default: none
+    #[serde(default)]
+    pub sinks: Vec<TMSink>,
+    /// Embedded HTTP control API
+    /// Default: disabled
+    #[serde(default)]
+    pub http: Option<TMHttpConfig>,
+    /// Glob patterns of files defining more programs, e.g. "/etc/taskmaster/conf.d/*.toml", a
+    /// program may only be defined once across all files
+    /// Default: none
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// File where the state of the programs is saved, the next taskmaster adopts the processes
+    /// of direct_output programs still running instead of launching them again, the processes
+    /// of the other programs are stopped and launched again since their output went through
+    /// pipes of the previous taskmaster, and so are the processes of programs with listen since
+    /// they hold the sockets taskmaster opens again
+    /// Default: none
+    #[serde(default)]
+    pub state_file: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TMHttpConfig {
+    /// "127.0.0.1:9001" or "unix:/path/to/taskmaster.sock", any other address than localhost
+    /// needs users
+    pub listen: String,
+    /// Permissions of the unix socket, e.g. 0o660
+    /// Default: 0o600
+    #[serde(default = "default_socket_mode")]
+    pub socket_mode: u32,
+    /// Owner of the unix socket, by name or uid
+    /// Default: taskmaster user
+    #[serde(default)]
+    pub socket_owner: Option<String>,
+    /// Group of the unix socket, by name or gid
+    /// Default: taskmaster group
+    #[serde(default)]
+    pub socket_group: Option<String>,
+    /// Serve Prometheus metrics on /metrics
+    /// Default: false
+    #[serde(default)]
+    pub metrics: bool,
+    /// Who may use the API, every request must authenticate as one of them
+    /// Default: none, anyone reaching the socket has full access
+    #[serde(default)]
+    pub users: Vec<TMHttpUser>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TMHttpUser {
+    pub name: String,
+    /// Authenticate with basic auth as name and this password
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Authenticate with an "Authorization: Bearer <token>" header
+    #[serde(default)]
+    pub token: Option<String>,
+    /// Authenticate the unix account called name by the peer credentials of the unix socket
+    /// Default: false
+    #[serde(default)]
+    pub peer: bool,
+    /// Commands the user may run
+    /// Default: every command
+    #[serde(default)]
+    pub allow: Vec<ApiAction>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiAction {
+    Status,
+    Output,
+    Start,
+    Stop,
+    Restart,
+    Signal,
+    Reload,
+    Metrics,
+}
+
+fn default_socket_mode() -> u32 {
+    0o600
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum TMSink {
+    /// Local syslog daemon
+    Syslog {
+        #[serde(default)]
+        format: SyslogFormat,
+        /// Default: /dev/log
+        #[serde(default = "default_syslog_socket")]
+        socket: String,
+    },
+    /// journald native protocol
+    Journald {
+        /// Default: /run/systemd/journal/socket
+        #[serde(default = "default_journald_socket")]
+        socket: String,
+    },
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogFormat {
+    #[default]
+    Rfc3164,
+    Rfc5424,
+}
+
+fn default_syslog_socket() -> String {
+    "/dev/log".to_string()
+}
+
+fn default_journald_socket() -> String {
+    "/run/systemd/journal/socket".to_string()
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Overlap {
+    #[default]
+    Skip,
+    Queue,
+    Replace,
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgramType {
+    #[default]
+    Service,
+    EventListener,
+    Oneshot,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_priority() -> i32 {
+    999
+}
+
+fn default_graceful_period() -> u32 {
+    10
+}
+
+fn default_rollout_timeout() -> u32 {
+    60
+}
+
+fn default_logfile_backups() -> u32 {
+    10
+}
+
+fn default_hook_rate_limit() -> u32 {
+    10
+}
+
+fn default_hook_rate_period() -> u32 {
+    60
 }
 
 #[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -54,10 +531,49 @@ pub struct TMProgramConfig {
     pub command: String,
     /// Argument to run the command with
     pub args: Vec<String>,
+    /// How many instances of the program to run
     pub process: u32,
-    /// Whether to start the program when taskmaster launches
+    /// Whether to start the program when taskmaster launches, ignored with a schedule
     /// Default: true
     pub autostart: bool,
+    /// Cron expression launching the program at those times, in local time, e.g. "*/5 * * * *"
+    /// or "@daily", the program then only runs again at its next time whatever autorestart
+    /// Default: none
+    #[serde(default)]
+    pub schedule: Option<Schedule>,
+    /// What to do when a scheduled run is due while the previous one is still running, skip by
+    /// default:
+    ///  - skip: do not run this time
+    ///  - queue: run once the previous one exits
+    ///  - replace: stop the previous one and run
+    #[serde(default)]
+    pub overlap: Overlap,
+    /// Programs are started from the lowest to the highest priority, and stopped the other way
+    /// Default: 999
+    #[serde(default = "default_priority")]
+    pub priority: i32,
+    /// Programs that must be running for health_time, or completed for oneshot programs, before
+    /// this one is started
+    /// Default: none
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    /// Sockets opened and held by taskmaster, "tcp:host:port" or "unix:/path", passed to the
+    /// program from fd 3 with LISTEN_FDS and LISTEN_PID set like systemd socket activation, a
+    /// reload changing it opens the new addresses and restarts the program
+    /// Default: none
+    #[serde(default)]
+    pub listen: Vec<String>,
+    /// What kind of program it is, service by default:
+    ///  - service: a long running program
+    ///  - eventlistener: a long running program receiving events on its stdin
+    ///  - oneshot: a task that is done once it exits with an exit_status, it is then never
+    ///    restarted
+    #[serde(default, rename = "type")]
+    pub program_type: ProgramType,
+    /// Events an eventlistener receives
+    /// Default: every event
+    #[serde(default)]
+    pub events: Vec<EventKind>,
     /// When to restart the program:
     ///  - always: Always restart the program, even on successful exits.
     ///  - never: Never restart the program.
@@ -68,26 +584,454 @@ pub struct TMProgramConfig {
     pub exit_status: Vec<c_int>,
     /// How many times a restart should be attempted before aborting
     pub number_restart: u32,
+    /// Delay between two restart attempts
+    #[serde(default)]
+    pub backoff: TMBackoff,
     /// How long the program should be running to be considered "successfully started" in secs.
     pub health_time: u32,
     /// Signal for graceful stop
     /// Default: SIGTERM
     pub stopsignal: String,
+    /// How long to wait after the stopsignal before killing the program in secs.
+    /// Default: 10
+    #[serde(default = "default_graceful_period")]
+    pub graceful_period: u32,
+    /// How long a rolling restart waits for a restarted instance to become healthy before
+    /// aborting, in secs.
+    /// Default: 60
+    #[serde(default = "default_rollout_timeout")]
+    pub rollout_timeout: u32,
+    /// Send the stopsignal to the whole process group of the program
+    /// Default: true
+    #[serde(default = "default_true")]
+    pub stopasgroup: bool,
+    /// Send SIGKILL to the whole process group of the program
+    /// Default: true
+    #[serde(default = "default_true")]
+    pub killasgroup: bool,
     /// Environment variables set before launching the program
     /// Default: Taskmaster environment
     ///TODO
     /// Working directory to set before launching the program
     /// Default: Taskmaster CWD
     pub cwd: Option<String>,
+    /// User to run the program as, by name or uid
+    /// Default: Taskmaster user
+    #[serde(default)]
+    pub user: Option<String>,
+    /// Group to run the program as, by name or gid
+    /// Default: primary group of user, required when user is a uid with no passwd entry, or
+    /// Taskmaster group when user is not set
+    #[serde(default)]
+    pub group: Option<String>,
+    /// Supplementary groups of the program, by name or gid
+    /// Default: none
+    #[serde(default)]
+    pub groups: Vec<String>,
     /// umask to set before launching the program
     /// Default: 022
     pub umask: Option<i32>,
+    /// File the program reads its stdin from
+    /// Default: /dev/null
+    #[serde(default)]
+    pub stdin: Option<String>,
+    /// Give the program a stdin pipe, a new one on each launch, that the attach command forwards
+    /// the terminal to, stdin is then ignored
+    /// Default: false
+    #[serde(default)]
+    pub attachable: bool,
+    /// Run the program on a pseudo-terminal, its stdin, stdout and stderr are then the pty and
+    /// its output is captured as stdout, not for eventlisteners nor with stdin
+    /// Default: false
+    #[serde(default)]
+    pub pty: bool,
+    /// Window size of the pty, attach resizes it to the terminal
+    /// Default: 24 rows, 80 cols
+    #[serde(default)]
+    pub pty_size: TMPtySize,
+    /// The program writes its stdout and stderr files itself instead of through taskmaster, so
+    /// it keeps running when taskmaster exits and can be adopted through the state file, the
+    /// files are then never rotated and the output is neither kept nor sent to sinks
+    /// Default: false
+    #[serde(default)]
+    pub direct_output: bool,
     ///Redirect stdout (optional)
     /// Default: Piped to taskmaster
     #[serde(default)]
     pub stdout: Option<String>,
+    /// Rotate the stdout file once it would grow over this size in bytes, 0 to never rotate
+    /// Default: 0
+    #[serde(default)]
+    pub stdout_logfile_maxbytes: u64,
+    /// How many rotated stdout files to keep
+    /// Default: 10
+    #[serde(default = "default_logfile_backups")]
+    pub stdout_logfile_backups: u32,
+    /// Gzip the rotated stdout files
+    /// Default: false
+    #[serde(default)]
+    pub stdout_logfile_compress: bool,
     ///Redirect stderr (optional)
     /// Default: Piped to taskmaster
     #[serde(default)]
     pub stderr: Option<String>,
+    /// Rotate the stderr file once it would grow over this size in bytes, 0 to never rotate
+    /// Default: 0
+    #[serde(default)]
+    pub stderr_logfile_maxbytes: u64,
+    /// How many rotated stderr files to keep
+    /// Default: 10
+    #[serde(default = "default_logfile_backups")]
+    pub stderr_logfile_backups: u32,
+    /// Gzip the rotated stderr files
+    /// Default: false
+    #[serde(default)]
+    pub stderr_logfile_compress: bool,
+    /// Where the output lines of the program are sent, on top of the stdout and stderr files
+    /// Default: global sinks
+    #[serde(default)]
+    pub sinks: Option<Vec<TMSink>>,
+    /// Resource limits applied to the program before exec
+    /// Default: inherited from taskmaster
+    #[serde(default)]
+    pub rlimits: TMRlimits,
+    /// Thresholds over which the program is restarted
+    /// Default: no thresholds
+    #[serde(default)]
+    pub watchdog: TMWatchdog,
+    /// Probe that must succeed for the program to be considered healthy
+    /// Default: healthy once running for health_time
+    #[serde(default)]
+    pub readiness: Option<TMProbe>,
+    /// Probe checked once the program is healthy, the program is restarted when it fails
+    /// failure_threshold times in a row
+    /// Default: none
+    #[serde(default)]
+    pub liveness: Option<TMProbe>,
+}
+
+impl TMProgramConfig {
+    /// Reject the options that cannot be used together
+    fn check(&self) -> Result<(), &'static str> {
+        if self.pty && self.program_type == ProgramType::EventListener {
+            return Err("an eventlistener cannot run on a pty, its stdin and stdout carry events");
+        }
+        if self.pty && self.stdin.is_some() {
+            return Err("stdin cannot be used with pty, the program reads the pty");
+        }
+        if self.direct_output && (self.pty || self.program_type == ProgramType::EventListener) {
+            return Err("direct_output cannot be used with pty nor by an eventlistener");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TMPtySize {
+    pub rows: u16,
+    pub cols: u16,
+}
+
+impl Default for TMPtySize {
+    fn default() -> Self {
+        Self { rows: 24, cols: 80 }
+    }
+}
+
+/// Resource limits, every limit set here is applied as both the soft and the hard limit
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct TMRlimits {
+    /// Max number of open file descriptors (RLIMIT_NOFILE)
+    pub nofile: Option<u64>,
+    /// Max size of the virtual address space in bytes (RLIMIT_AS)
+    #[serde(rename = "as")]
+    pub address_space: Option<u64>,
+    /// Max size of a core dump in bytes (RLIMIT_CORE)
+    pub core: Option<u64>,
+    /// Max number of processes for the user running the program (RLIMIT_NPROC)
+    pub nproc: Option<u64>,
+    /// Max CPU time in secs (RLIMIT_CPU)
+    pub cpu: Option<u64>,
+}
+
+/// Delay before a restart attempt: initial_delay * multiplier ^ (attempt - 1), capped at max_delay
+/// and shifted by up to jitter percent either way
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct TMBackoff {
+    /// Delay before the first attempt in millisecs.
+    /// Default: 1000
+    pub initial_delay: u64,
+    /// Default: 2
+    pub multiplier: u32,
+    /// Max delay before an attempt in millisecs.
+    /// Default: 60000
+    pub max_delay: u64,
+    /// Random variation of the delay in percent
+    /// Default: 10
+    pub jitter: u32,
+}
+
+impl Default for TMBackoff {
+    fn default() -> Self {
+        Self {
+            initial_delay: 1000,
+            multiplier: 2,
+            max_delay: 60000,
+            jitter: 10,
+        }
+    }
+}
+
+/// Thresholds checked by the supervisor, the program is gracefully restarted when one is exceeded
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct TMWatchdog {
+    /// Max resident memory in bytes
+    pub max_rss: Option<u64>,
+    /// Max CPU usage in percent of one core
+    pub max_cpu: Option<u32>,
+    /// How long the CPU usage must stay over max_cpu before restarting in secs.
+    /// Default: 10
+    pub cpu_period: u32,
+}
+
+impl Default for TMWatchdog {
+    fn default() -> Self {
+        Self {
+            max_rss: None,
+            max_cpu: None,
+            cpu_period: 10,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeCheck {
+    /// Run a command, the probe succeeds if it exits with 0
+    Exec(Vec<String>),
+    /// Connect to "host:port", or to a port of localhost
+    Tcp(String),
+    /// GET an "http://host:port/path" url, the probe succeeds on a 2xx or 3xx status
+    Http(String),
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TMProbe {
+    #[serde(flatten)]
+    pub check: ProbeCheck,
+    /// Time between two probes in secs.
+    /// Default: 10
+    #[serde(default = "default_probe_interval")]
+    pub interval: u32,
+    /// Time after which the probe is failed in secs.
+    /// Default: 1
+    #[serde(default = "default_probe_timeout")]
+    pub timeout: u32,
+    /// How many probes in a row must fail for the program to be considered failing
+    /// Default: 3
+    #[serde(default = "default_probe_failure_threshold")]
+    pub failure_threshold: u32,
+}
+
+fn default_probe_interval() -> u32 {
+    10
+}
+
+fn default_probe_timeout() -> u32 {
+    1
+}
+
+fn default_probe_failure_threshold() -> u32 {
+    3
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A program with only the required fields and the given extra lines
+    fn program(name: &str, extra: &str) -> String {
+        format!(
+            "[programs.{name}]\n\
+             command = \"true\"\n\
+             args = []\n\
+             process = 1\n\
+             autostart = true\n\
+             autorestart = \"never\"\n\
+             number_restart = 0\n\
+             health_time = 1\n\
+             stopsignal = \"SIGTERM\"\n\
+             {extra}\n"
+        )
+    }
+
+    /// Load a config.toml made of the given programs and global lines
+    fn load(programs: &[String], global: &str) -> Result<TMConfig, ConfigError> {
+        let dir = tempfile::tempdir().unwrap();
+        load_in(dir.path(), programs, global)
+    }
+
+    fn load_in(dir: &Path, programs: &[String], global: &str) -> Result<TMConfig, ConfigError> {
+        let path = dir.join("config.toml");
+        let content = format!(
+            "{}[global]\nlogfile = \"log\"\n{global}\n",
+            programs.concat()
+        );
+        std::fs::write(&path, content).unwrap();
+        TMConfig::load(path.to_str().unwrap())
+    }
+
+    /// Write a file in the conf.d directory next to the config file
+    fn include(dir: &Path, file: &str, content: &str) {
+        std::fs::create_dir_all(dir.join("conf.d")).unwrap();
+        std::fs::write(dir.join("conf.d").join(file), content).unwrap();
+    }
+
+    const INCLUDE: &str = "include = [\"conf.d/*.toml\"]";
+
+    #[test]
+    fn start_order_dependencies_first() {
+        let config = load(
+            &[
+                program("web", "depends_on = [\"db\", \"cache\"]"),
+                program("db", ""),
+                program("cache", "depends_on = [\"db\"]"),
+            ],
+            "",
+        )
+        .unwrap();
+        assert_eq!(config.start_order().unwrap(), ["db", "cache", "web"]);
+    }
+
+    #[test]
+    fn start_order_priority_then_name() {
+        let config = load(
+            &[
+                program("b", ""),
+                program("a", ""),
+                program("c", "priority = 1"),
+            ],
+            "",
+        )
+        .unwrap();
+        assert_eq!(config.start_order().unwrap(), ["c", "a", "b"]);
+    }
+
+    #[test]
+    fn start_order_unknown_dependency() {
+        let result = load(&[program("web", "depends_on = [\"db\"]")], "");
+        assert!(matches!(
+            result,
+            Err(ConfigError::UnknownDependency(program, dependency))
+                if program == "web" && dependency == "db"
+        ));
+    }
+
+    #[test]
+    fn start_order_cycle() {
+        let result = load(
+            &[
+                program("a", "depends_on = [\"b\"]"),
+                program("b", "depends_on = [\"c\"]"),
+                program("c", "depends_on = [\"a\"]"),
+                program("d", "depends_on = [\"a\"]"),
+            ],
+            "",
+        );
+        let Err(ConfigError::DependencyCycle(cycle)) = result else {
+            panic!("expected a dependency cycle");
+        };
+        // the cycle is closed and d, which only depends on it, is not part of it
+        assert_eq!(cycle.len(), 4);
+        assert_eq!(cycle.first(), cycle.last());
+        assert!(!cycle.contains(&"d".to_string()));
+    }
+
+    #[test]
+    fn start_order_self_dependency() {
+        let result = load(&[program("a", "depends_on = [\"a\"]")], "");
+        assert!(matches!(result, Err(ConfigError::DependencyCycle(x)) if x == ["a", "a"]));
+    }
+
+    #[test]
+    fn include_merges_programs() {
+        let dir = tempfile::tempdir().unwrap();
+        include(dir.path(), "a.toml", &program("a", ""));
+        include(
+            dir.path(),
+            "b.toml",
+            &(program("b", "") + &program("c", "")),
+        );
+        include(dir.path(), "ignored.conf", &program("d", ""));
+        let config = load_in(dir.path(), &[program("main", "")], INCLUDE).unwrap();
+        let mut names: Vec<&String> = config.programs.keys().collect();
+        names.sort();
+        assert_eq!(names, ["a", "b", "c", "main"]);
+    }
+
+    #[test]
+    fn include_without_match() {
+        let config = load(&[program("main", "")], INCLUDE).unwrap();
+        assert_eq!(config.programs.len(), 1);
+    }
+
+    #[test]
+    fn include_skips_the_config_file() {
+        let config = load(&[program("main", "")], "include = [\"*.toml\"]").unwrap();
+        assert_eq!(config.programs.len(), 1);
+    }
+
+    #[test]
+    fn include_duplicate_with_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        include(dir.path(), "a.toml", &program("main", ""));
+        let result = load_in(dir.path(), &[program("main", "")], INCLUDE);
+        let Err(ConfigError::DuplicateProgram(name, first, second)) = result else {
+            panic!("expected a duplicate program");
+        };
+        assert_eq!(name, "main");
+        assert!(first.ends_with("config.toml"), "{first}");
+        assert!(second.ends_with("conf.d/a.toml"), "{second}");
+    }
+
+    #[test]
+    fn include_duplicate_across_included_files() {
+        let dir = tempfile::tempdir().unwrap();
+        include(dir.path(), "a.toml", &program("web", ""));
+        include(dir.path(), "b.toml", &program("web", ""));
+        let result = load_in(dir.path(), &[], INCLUDE);
+        let Err(ConfigError::DuplicateProgram(name, first, second)) = result else {
+            panic!("expected a duplicate program");
+        };
+        assert_eq!(name, "web");
+        assert!(first.ends_with("conf.d/a.toml"), "{first}");
+        assert!(second.ends_with("conf.d/b.toml"), "{second}");
+    }
+
+    #[test]
+    fn include_rejects_other_sections() {
+        let dir = tempfile::tempdir().unwrap();
+        include(dir.path(), "a.toml", "[global]\nlogfile = \"other\"\n");
+        let result = load_in(dir.path(), &[], INCLUDE);
+        assert!(matches!(result, Err(ConfigError::Parse(file, _)) if file.ends_with("a.toml")));
+    }
+
+    #[test]
+    fn invalid_pty_combinations() {
+        for extra in [
+            "pty = true\ntype = \"eventlistener\"",
+            "pty = true\nstdin = \"input\"",
+            "pty = true\ndirect_output = true",
+            "type = \"eventlistener\"\ndirect_output = true",
+        ] {
+            let result = load(&[program("web", extra)], "");
+            assert!(
+                matches!(result, Err(ConfigError::InvalidProgram(ref x, _)) if x == "web"),
+                "{extra}"
+            );
+        }
+        assert!(load(&[program("web", "pty = true")], "").is_ok());
+    }
 }
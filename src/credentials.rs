@@ -0,0 +1,144 @@
+use std::ffi::{CStr, CString};
+use std::io;
+
+use libc::{c_char, gid_t, uid_t};
+
+use crate::config::TMProgramConfig;
+
+/// User and groups a program runs as, resolved by taskmaster before forking
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub uid: uid_t,
+    pub gid: gid_t,
+    pub groups: Vec<gid_t>,
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+/// Run a reentrant passwd or group lookup, growing its buffer until the entry fits, None when
+/// there is no such entry or the lookup failed
+fn lookup<T>(
+    mut call: impl FnMut(*mut T, *mut c_char, usize, *mut *mut T) -> libc::c_int,
+) -> Option<(T, Vec<c_char>)> {
+    let mut buf: Vec<c_char> = vec![0; 1024];
+    loop {
+        let mut entry: T = unsafe { std::mem::zeroed() };
+        let mut result: *mut T = std::ptr::null_mut();
+        match call(&mut entry, buf.as_mut_ptr(), buf.len(), &mut result) {
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            0 if !result.is_null() => return Some((entry, buf)),
+            _ => return None,
+        }
+    }
+}
+
+/// Entry of the passwd database by name, or by uid when there is no name, the strings of the
+/// entry point into the returned buffer
+fn passwd(name: Option<&CStr>, uid: uid_t) -> Option<(libc::passwd, Vec<c_char>)> {
+    lookup(|entry, buf, len, result| unsafe {
+        match name {
+            Some(x) => libc::getpwnam_r(x.as_ptr(), entry, buf, len, result),
+            None => libc::getpwuid_r(uid, entry, buf, len, result),
+        }
+    })
+}
+
+/// Resolve a user given by name or id, return its uid and primary group
+pub fn resolve_user(user: &str) -> io::Result<(uid_t, Option<gid_t>)> {
+    let entry = match user.parse::<uid_t>() {
+        Ok(uid) => passwd(None, uid),
+        Err(_) => {
+            let name = CString::new(user).map_err(|_| invalid(format!("invalid user {user}")))?;
+            passwd(Some(&name), 0)
+        }
+    };
+    match (entry, user.parse::<uid_t>()) {
+        (Some((entry, _)), _) => Ok((entry.pw_uid, Some(entry.pw_gid))),
+        // a numeric id does not need to exist in the passwd database
+        (None, Ok(uid)) => Ok((uid, None)),
+        (None, Err(_)) => Err(invalid(format!("unknown user {user}"))),
+    }
+}
+
+/// Resolve a group given by name or id
+pub fn resolve_group(group: &str) -> io::Result<gid_t> {
+    if let Ok(gid) = group.parse::<gid_t>() {
+        return Ok(gid);
+    }
+    let name = CString::new(group).map_err(|_| invalid(format!("invalid group {group}")))?;
+    let entry = lookup(|entry, buf, len, result| unsafe {
+        libc::getgrnam_r(name.as_ptr(), entry, buf, len, result)
+    });
+    match entry {
+        Some((entry, _)) => Ok(entry.gr_gid),
+        None => Err(invalid(format!("unknown group {group}"))),
+    }
+}
+
+/// Name of the account with the given uid
+pub fn user_name(uid: uid_t) -> Option<String> {
+    let (entry, _buf) = passwd(None, uid)?;
+    let name = unsafe { CStr::from_ptr(entry.pw_name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+impl Credentials {
+    /// Resolve the credentials of a program, None if it runs as taskmaster's user
+    pub fn resolve(config: &TMProgramConfig) -> io::Result<Option<Self>> {
+        if config.user.is_none() && config.group.is_none() && config.groups.is_empty() {
+            return Ok(None);
+        }
+        let (uid, user_gid) = match &config.user {
+            None => (unsafe { libc::geteuid() }, None),
+            Some(x) => resolve_user(x)?,
+        };
+        let gid = match (&config.group, &config.user, user_gid) {
+            (Some(x), _, _) => resolve_group(x)?,
+            (None, None, _) => unsafe { libc::getegid() },
+            (None, Some(_), Some(x)) => x,
+            // keeping the group of taskmaster, root when it runs as root, is never what is meant
+            (None, Some(x), None) => {
+                return Err(invalid(format!(
+                    "user {x} has no passwd entry, its group must be set"
+                )))
+            }
+        };
+        let mut groups = vec![gid];
+        for group in config.groups.iter() {
+            groups.push(resolve_group(group)?);
+        }
+        let credentials = Self { uid, gid, groups };
+        credentials.check_privilege()?;
+        Ok(Some(credentials))
+    }
+
+    /// Changing user or groups is only allowed when taskmaster runs as root
+    fn check_privilege(&self) -> io::Result<()> {
+        let (euid, egid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        if euid == 0 || (self.uid == euid && self.gid == egid && self.groups == [egid]) {
+            return Ok(());
+        }
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "taskmaster must run as root to change the user or groups of a program",
+        ))
+    }
+
+    /// Drop to the credentials, meant to be called in the child between fork and exec so it only
+    /// use async-signal-safe functions
+    pub fn apply(&self) -> io::Result<()> {
+        unsafe {
+            if libc::geteuid() == 0
+                && libc::setgroups(self.groups.len() as _, self.groups.as_ptr()) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::setgid(self.gid) != 0 || libc::setuid(self.uid) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
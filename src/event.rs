@@ -0,0 +1,221 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Display, Formatter};
+use std::os::unix::process::ExitStatusExt;
+use std::process::{ExitStatus, Stdio};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::config::{HookAction, TMHook};
+use crate::program::TMProgram;
+use crate::{http_client, logger, CONFIG};
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum EventKind {
+    ProcessStarted,
+    /// The program exited on its own
+    ProcessExited,
+    /// The program exited after being stopped by taskmaster
+    ProcessStopped,
+    /// The program is waiting before a restart attempt
+    ProcessBackoff,
+    /// taskmaster gave up restarting the program
+    ProcessFatal,
+    Reloaded,
+}
+
+impl Display for EventKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::ProcessStarted => "PROCESS_STARTED",
+            Self::ProcessExited => "PROCESS_EXITED",
+            Self::ProcessStopped => "PROCESS_STOPPED",
+            Self::ProcessBackoff => "PROCESS_BACKOFF",
+            Self::ProcessFatal => "PROCESS_FATAL",
+            Self::Reloaded => "RELOADED",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub kind: EventKind,
+    /// Details of the event, like "program" or "exit_code"
+    pub fields: Vec<(&'static str, Value)>,
+}
+
+impl Event {
+    pub fn new(kind: EventKind) -> Self {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs());
+        Self {
+            kind,
+            fields: vec![("time", time.into())],
+        }
+    }
+
+    pub fn with(mut self, key: &'static str, value: impl Into<Value>) -> Self {
+        self.fields.push((key, value.into()));
+        self
+    }
+
+    /// Add how the program exited
+    pub fn with_exit(self, status: ExitStatus) -> Self {
+        match (status.code(), status.signal()) {
+            (Some(code), _) => self.with("exit_code", code),
+            (None, Some(signal)) => self.with("signal", signal),
+            (None, None) => self,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut map = Map::new();
+        map.insert("event".to_string(), self.kind.to_string().into());
+        for (key, value) in self.fields.iter() {
+            map.insert(key.to_string(), value.clone());
+        }
+        Value::Object(map).to_string()
+    }
+
+    /// TASKMASTER_EVENT=PROCESS_EXITED, TASKMASTER_EXIT_CODE=1...
+    pub fn to_env(&self) -> Vec<(String, String)> {
+        let mut env = vec![("TASKMASTER_EVENT".to_string(), self.kind.to_string())];
+        for (key, value) in self.fields.iter() {
+            let value = match value {
+                Value::String(x) => x.clone(),
+                x => x.to_string(),
+            };
+            env.push((format!("TASKMASTER_{}", key.to_uppercase()), value));
+        }
+        env
+    }
+}
+
+impl TMProgram {
+    /// Event about this program
+    pub fn event(&self, kind: EventKind) -> Event {
+        let event = Event::new(kind)
+            .with("program", self.name.as_str())
+            .with("instance", self.instance);
+        match &self.child {
+            None => event,
+            Some(x) => event.with("pid", x.id()),
+        }
+    }
+}
+
+static EVENTS: OnceLock<UnboundedSender<Event>> = OnceLock::new();
+
+/// Event listeners, with the event kinds they want
+static LISTENERS: Mutex<Vec<(Vec<EventKind>, Sender<Event>)>> = Mutex::new(Vec::new());
+
+/// Receive the events of the given kinds, or every event when empty
+pub fn subscribe(kinds: Vec<EventKind>) -> Receiver<Event> {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    LISTENERS.lock().unwrap().push((kinds, sender));
+    receiver
+}
+
+/// Queue an event for the hooks and listeners, never blocks
+pub fn emit(event: Event) {
+    if let Some(sender) = EVENTS.get() {
+        let _ = sender.send(event);
+    }
+}
+
+/// Sliding window of the times a hook ran for an event kind
+#[derive(Default)]
+struct RateLimit {
+    runs: VecDeque<Instant>,
+    suppressed: bool,
+}
+
+/// Start running the hooks and feeding the listeners with the emitted events
+pub fn start() {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    if EVENTS.set(sender).is_ok() {
+        tokio::spawn(dispatch(receiver));
+    }
+}
+
+async fn dispatch(mut receiver: UnboundedReceiver<Event>) {
+    let mut limits: HashMap<(usize, EventKind), RateLimit> = HashMap::new();
+    while let Some(event) = receiver.recv().await {
+        // a listener is gone once its receiver is dropped
+        LISTENERS.lock().unwrap().retain(|(kinds, sender)| {
+            (!kinds.is_empty() && !kinds.contains(&event.kind))
+                || sender.send(event.clone()).is_ok()
+        });
+        let hooks = CONFIG.lock().unwrap().hooks.clone();
+        for (idx, hook) in hooks.into_iter().enumerate() {
+            if !hook.events.is_empty() && !hook.events.contains(&event.kind) {
+                continue;
+            }
+            let limit = limits.entry((idx, event.kind)).or_default();
+            let period = Duration::from_secs(hook.rate_period.into());
+            while limit.runs.front().is_some_and(|x| x.elapsed() >= period) {
+                limit.runs.pop_front();
+            }
+            if limit.runs.len() >= hook.rate_limit as usize {
+                if !limit.suppressed {
+                    logger::error(&format!(
+                        "hook {idx} ran {} times for {} in {}s, suppressing it",
+                        hook.rate_limit, event.kind, hook.rate_period
+                    ));
+                }
+                limit.suppressed = true;
+                continue;
+            }
+            limit.suppressed = false;
+            limit.runs.push_back(Instant::now());
+            tokio::spawn(run_hook(hook, event.clone()));
+        }
+    }
+}
+
+async fn run_hook(hook: TMHook, event: Event) {
+    let result = match &hook.action {
+        HookAction::Command(command) => run_command(command, &event).await,
+        HookAction::Url(url) => {
+            match http_client::request("POST", url, Some(&event.to_json())).await {
+                Some(200..300) => Ok(()),
+                Some(x) => Err(format!("{url} answered {x}")),
+                None => Err(format!("failed to post to {url}")),
+            }
+        }
+    };
+    if let Err(e) = result {
+        logger::error(&format!("hook for {} failed: {e}", event.kind));
+    }
+}
+
+/// Run a command with the event in its environment and as JSON on its stdin
+async fn run_command(command: &[String], event: &Event) -> Result<(), String> {
+    let Some((program, args)) = command.split_first() else {
+        return Err("empty command".to_string());
+    };
+    let mut child = Command::new(program)
+        .args(args)
+        .envs(event.to_env())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("failed to run {program}: {e}"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(event.to_json().as_bytes()).await;
+    }
+    match child.wait().await {
+        Ok(x) if x.success() => Ok(()),
+        Ok(x) => Err(format!("{program} {x}")),
+        Err(e) => Err(format!("failed to wait {program}: {e}")),
+    }
+}
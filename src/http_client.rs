@@ -0,0 +1,38 @@
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Send a request to an "http://host:port/path" url, with an optional JSON body, and return the
+/// status code of the response
+pub async fn request(method: &str, url: &str, json: Option<&str>) -> Option<u16> {
+    let url = url.strip_prefix("http://")?;
+    let (host, path) = match url.find('/') {
+        Some(idx) => url.split_at(idx),
+        None => (url, "/"),
+    };
+    let address = match host.contains(':') {
+        true => host.to_string(),
+        false => format!("{host}:80"),
+    };
+    let mut stream = TcpStream::connect(address).await.ok()?;
+    let mut request = format!("{method} {path} HTTP/1.0\r\nHost: {host}\r\nConnection: close\r\n");
+    if let Some(json) = json {
+        request += &format!(
+            "Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{json}",
+            json.len()
+        );
+    } else {
+        request += "\r\n";
+    }
+    stream.write_all(request.as_bytes()).await.ok()?;
+    let mut response = [0; 32];
+    let mut len = 0;
+    while len < response.len() {
+        match stream.read(&mut response[len..]).await.ok()? {
+            0 => break,
+            x => len += x,
+        }
+    }
+    // "HTTP/1.1 200 OK"
+    let response = String::from_utf8_lossy(&response[..len]);
+    response.split_whitespace().nth(1)?.parse().ok()
+}
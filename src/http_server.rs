@@ -0,0 +1,618 @@
+use std::fs::Permissions;
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use libc::uid_t;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, UnixListener};
+
+use crate::command::{CommandError, CommandUser, TargetResult};
+use crate::config::{ApiAction, TMHttpConfig, TMHttpUser};
+use crate::credentials::{resolve_group, resolve_user, user_name};
+use crate::program::TMProgram;
+use crate::signal::parse_signal;
+use crate::target::Target;
+use crate::{logger, metrics, reload};
+
+/// Bytes of output returned when the request does not ask for a size
+const DEFAULT_OUTPUT_BYTES: usize = 4096;
+
+/// Largest request body accepted
+const MAX_BODY: usize = 64 * 1024;
+
+/// Longest request line or header accepted
+const MAX_LINE: usize = 8 * 1024;
+
+/// How long a client has to send its whole request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Request {
+    method: String,
+    path: String,
+    authorization: Option<String>,
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn ok(body: Value) -> Self {
+        Self::json(200, body)
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self::json(status, json!({ "error": message }))
+    }
+
+    fn json(status: u16, body: Value) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: format!("{body}\n"),
+        }
+    }
+
+    fn text(body: String) -> Self {
+        Self {
+            status: 200,
+            content_type: "text/plain; version=0.0.4",
+            body,
+        }
+    }
+}
+
+/// Serve the control API on a TCP address or on "unix:/path"
+pub async fn serve(config: TMHttpConfig, programs: Arc<Mutex<Vec<TMProgram>>>) {
+    let config = Arc::new(config);
+    if let Some(path) = config.listen.strip_prefix("unix:") {
+        let listener = match bind_unix(path, &config) {
+            Ok(x) => x,
+            Err(e) => return logger::error(&format!("http: failed to listen on {path}: {e}")),
+        };
+        logger::info(&format!("http: listening on {}", config.listen));
+        while let Ok((stream, _)) = listener.accept().await {
+            let peer = stream.peer_cred().ok().map(|x| x.uid());
+            tokio::spawn(handle_connection(
+                stream,
+                peer,
+                config.clone(),
+                programs.clone(),
+            ));
+        }
+    } else {
+        let listener = match TcpListener::bind(&config.listen).await {
+            Ok(x) => x,
+            Err(e) => {
+                return logger::error(&format!("http: failed to listen on {}: {e}", config.listen))
+            }
+        };
+        let loopback = listener.local_addr().is_ok_and(|x| x.ip().is_loopback());
+        if !loopback && config.users.is_empty() {
+            return logger::error(&format!(
+                "http: refusing to listen on {} without users, anyone reaching it would control \
+                 taskmaster, listen on localhost or a unix socket or add users",
+                config.listen
+            ));
+        }
+        logger::info(&format!("http: listening on {}", config.listen));
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(handle_connection(
+                stream,
+                None,
+                config.clone(),
+                programs.clone(),
+            ));
+        }
+    }
+}
+
+/// Bind the socket in a directory only taskmaster can enter and move it to path once its
+/// permissions and owner are set, so nobody connects before they apply
+fn bind_unix(path: &str, config: &TMHttpConfig) -> io::Result<UnixListener> {
+    let dir = format!("{path}.tmp");
+    let tmp = format!("{dir}/socket");
+    // left by a previous run
+    let _ = std::fs::remove_file(&tmp);
+    let _ = std::fs::remove_dir(&dir);
+    std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let listener = UnixListener::bind(&tmp)
+        .and_then(|x| secure_socket(&tmp, config).map(|_| x))
+        .and_then(|x| std::fs::rename(&tmp, path).map(|_| x));
+    if listener.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    let _ = std::fs::remove_dir(&dir);
+    listener
+}
+
+fn secure_socket(path: &str, config: &TMHttpConfig) -> io::Result<()> {
+    std::fs::set_permissions(path, Permissions::from_mode(config.socket_mode))?;
+    if config.socket_owner.is_none() && config.socket_group.is_none() {
+        return Ok(());
+    }
+    let uid = match &config.socket_owner {
+        Some(x) => Some(resolve_user(x)?.0),
+        None => None,
+    };
+    let gid = match &config.socket_group {
+        Some(x) => Some(resolve_group(x)?),
+        None => None,
+    };
+    std::os::unix::fs::chown(path, uid, gid)
+}
+
+/// peer is the uid of the client when it connected through the unix socket
+async fn handle_connection(
+    stream: impl AsyncRead + AsyncWrite + Unpin,
+    peer: Option<uid_t>,
+    config: Arc<TMHttpConfig>,
+    programs: Arc<Mutex<Vec<TMProgram>>>,
+) {
+    let mut stream = BufReader::new(stream);
+    let response = match tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await {
+        Ok(Some(request)) => handle(&request, peer, &config, &programs),
+        Ok(None) => Response::error(400, "malformed request"),
+        Err(_) => Response::error(408, "request timeout"),
+    };
+    let challenge = match response.status {
+        401 => "WWW-Authenticate: Basic realm=\"taskmaster\"\r\n",
+        _ => "",
+    };
+    let head = format!(
+        "HTTP/1.0 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{challenge}Connection: close\r\n\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len()
+    );
+    let stream = stream.get_mut();
+    let _ = stream.write_all(head.as_bytes()).await;
+    let _ = stream.write_all(response.body.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+/// Read a line of at most MAX_LINE bytes, None when it is longer or the stream failed
+async fn read_line(stream: &mut (impl AsyncBufReadExt + Unpin)) -> Option<String> {
+    let mut line = String::new();
+    let read = stream
+        .take(MAX_LINE as u64)
+        .read_line(&mut line)
+        .await
+        .ok()?;
+    if read == MAX_LINE && !line.ends_with('\n') {
+        return None;
+    }
+    Some(line)
+}
+
+/// Read the request line and headers, and skip the body
+async fn read_request(stream: &mut (impl AsyncBufReadExt + Unpin)) -> Option<Request> {
+    let line = read_line(stream).await?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+    let mut content_length = 0;
+    let mut authorization = None;
+    loop {
+        let header = read_line(stream).await?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().ok()?;
+            } else if name.eq_ignore_ascii_case("authorization") {
+                authorization = Some(value.trim().to_string());
+            }
+        }
+    }
+    if content_length > MAX_BODY {
+        return None;
+    }
+    let mut body = vec![0; content_length];
+    stream.read_exact(&mut body).await.ok()?;
+    Some(Request {
+        method,
+        path,
+        authorization,
+    })
+}
+
+fn handle(
+    request: &Request,
+    peer: Option<uid_t>,
+    config: &TMHttpConfig,
+    programs: &Mutex<Vec<TMProgram>>,
+) -> Response {
+    let (path, query) = request.path.split_once('?').unwrap_or((&request.path, ""));
+    let segments: Vec<&str> = path.split('/').filter(|x| !x.is_empty()).collect();
+    let action = match required_action(&request.method, &segments) {
+        Some(ApiAction::Metrics) if !config.metrics => None,
+        x => x,
+    };
+    let Some(action) = action else {
+        return match segments.as_slice() {
+            ["metrics"] if !config.metrics => Response::error(404, "not found"),
+            ["programs", ..] | ["reload"] | ["metrics"] => {
+                Response::error(405, "method not allowed")
+            }
+            _ => Response::error(404, "not found"),
+        };
+    };
+    if !config.users.is_empty() {
+        let Some(user) = authenticate(request, peer, &config.users) else {
+            return Response::error(401, "authentication required");
+        };
+        if !user.allow.is_empty() && !user.allow.contains(&action) {
+            logger::info(&format!(
+                "http: {} denied {} {path}",
+                user.name, request.method
+            ));
+            return Response::error(403, "command not allowed");
+        }
+    }
+    route(&request.method, &segments, query, programs)
+}
+
+fn required_action(method: &str, segments: &[&str]) -> Option<ApiAction> {
+    match (method, segments) {
+        ("GET", ["programs"] | ["programs", _]) => Some(ApiAction::Status),
+        ("GET", ["programs", _, "output", "stdout" | "stderr"]) => Some(ApiAction::Output),
+        ("POST", ["programs", _, "start"]) => Some(ApiAction::Start),
+        ("POST", ["programs", _, "stop"]) => Some(ApiAction::Stop),
+        ("POST", ["programs", _, "restart"]) => Some(ApiAction::Restart),
+        ("POST", ["programs", _, "signal", _]) => Some(ApiAction::Signal),
+        ("POST", ["reload"]) => Some(ApiAction::Reload),
+        ("GET", ["metrics"]) => Some(ApiAction::Metrics),
+        _ => None,
+    }
+}
+
+/// Find the user the request authenticates as, by its Authorization header or else by the peer
+/// credentials of the unix socket
+fn authenticate<'a>(
+    request: &Request,
+    peer: Option<uid_t>,
+    users: &'a [TMHttpUser],
+) -> Option<&'a TMHttpUser> {
+    let Some(authorization) = &request.authorization else {
+        let name = user_name(peer?)?;
+        return users.iter().find(|x| x.peer && x.name == name);
+    };
+    let (scheme, credentials) = authorization.split_once(' ')?;
+    let credentials = credentials.trim();
+    if scheme.eq_ignore_ascii_case("bearer") {
+        return users.iter().find(|x| {
+            x.token
+                .as_deref()
+                .is_some_and(|x| secure_eq(x, credentials))
+        });
+    }
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = String::from_utf8(decode_base64(credentials)?).ok()?;
+    let (name, password) = decoded.split_once(':')?;
+    users.iter().find(|x| {
+        x.name == name
+            && x.password
+                .as_deref()
+                .is_some_and(|x| secure_eq(x, password))
+    })
+}
+
+/// Compare secrets in a time that does not depend on where they differ
+fn secure_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+fn decode_base64(input: &str) -> Option<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in input.trim_end_matches('=').bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = ((buffer << 6) | value as u32) & 0xffff;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+        }
+    }
+    Some(output)
+}
+
+fn route(
+    method: &str,
+    segments: &[&str],
+    query: &str,
+    programs: &Mutex<Vec<TMProgram>>,
+) -> Response {
+    let programs = &mut programs.lock().unwrap();
+    match (method, segments) {
+        ("GET", ["programs"]) => list(programs, &Target::All),
+        ("GET", ["programs", target]) => list(programs, &Target::from(*target)),
+        ("POST", ["programs", target, "start"]) => {
+            run(programs, CommandUser::Launch(Target::from(*target)))
+        }
+        ("POST", ["programs", target, "stop"]) => {
+            run(programs, CommandUser::Kill(Target::from(*target)))
+        }
+        ("POST", ["programs", target, "restart"]) => match query.split('&').any(|x| x == "rolling")
+        {
+            true => run(programs, CommandUser::RollingRestart(Target::from(*target))),
+            false => run(programs, CommandUser::Restart(Target::from(*target))),
+        },
+        ("POST", ["programs", target, "signal", signal]) => match parse_signal(signal) {
+            Some(x) => run(programs, CommandUser::Signal(Target::from(*target), x)),
+            None => Response::error(400, "unknown signal"),
+        },
+        ("GET", ["programs", target, "output", stream @ ("stdout" | "stderr")]) => {
+            output(programs, &Target::from(*target), stream, query)
+        }
+        ("POST", ["reload"]) => match reload(programs) {
+            Ok(()) => Response::ok(json!({ "reloaded": true })),
+            Err(e) => Response::error(500, &e.to_string()),
+        },
+        ("GET", ["metrics"]) => Response::text(metrics::render(programs)),
+        _ => Response::error(404, "not found"),
+    }
+}
+
+fn list(programs: &mut [TMProgram], target: &Target) -> Response {
+    let matching: Vec<Value> = programs
+        .iter_mut()
+        .enumerate()
+        .filter(|(idx, program)| target.matches(*idx, program))
+        .map(|(idx, program)| program_json(idx, program))
+        .collect();
+    if matching.is_empty() {
+        return Response::error(404, "no program matches the target");
+    }
+    Response::ok(Value::Array(matching))
+}
+
+fn program_json(idx: usize, program: &mut TMProgram) -> Value {
+    let state = program.state_label();
+    let pid = match program.is_running() {
+        true => program.child.as_ref().map(|x| x.id()),
+        false => None,
+    };
+    json!({
+        "index": idx,
+        "name": program.name,
+        "instance": program.instance,
+        "groups": program.groups,
+        "state": state,
+        "pid": pid,
+        "restarts": program.restarts,
+        "uptime": pid.and(program.started_at).map(|x| x.elapsed().as_secs()),
+    })
+}
+
+fn run(programs: &mut [TMProgram], command: CommandUser) -> Response {
+    match command.apply(programs) {
+        Ok(results) => Response::ok(Value::Array(results.into_iter().map(result_json).collect())),
+        Err(e) => command_error(e),
+    }
+}
+
+fn result_json((name, result): TargetResult) -> Value {
+    match result {
+        Ok(()) => json!({ "name": name, "ok": true }),
+        Err(e) => json!({ "name": name, "ok": false, "error": e.to_string() }),
+    }
+}
+
+fn command_error(error: CommandError) -> Response {
+    match error {
+        CommandError::WrongIndex | CommandError::UnknownTarget => {
+            Response::error(404, "no program matches the target")
+        }
+        CommandError::RolloutInProgress => {
+            Response::error(409, "a rolling restart is already in progress")
+        }
+        e => Response::error(500, &e.to_string()),
+    }
+}
+
+/// Last bytes written by a single instance on stdout or stderr, "?bytes=N" sets how many
+fn output(programs: &mut [TMProgram], target: &Target, stream: &str, query: &str) -> Response {
+    let bytes = query
+        .split('&')
+        .find_map(|x| x.strip_prefix("bytes="))
+        .map_or(Some(DEFAULT_OUTPUT_BYTES), |x| x.parse().ok());
+    let Some(bytes) = bytes else {
+        return Response::error(400, "invalid bytes");
+    };
+    let mut matching = programs
+        .iter()
+        .enumerate()
+        .filter(|(idx, program)| target.matches(*idx, program));
+    let program = match (matching.next(), matching.next()) {
+        (Some((_, x)), None) => x,
+        (None, _) => return Response::error(404, "no program matches the target"),
+        (Some(_), Some(_)) => return Response::error(400, "target matches several instances"),
+    };
+    let tail = match stream {
+        "stdout" => &program.stdout_tail,
+        _ => &program.stderr_tail,
+    };
+    let data = tail.lock().unwrap().last(bytes);
+    Response::ok(json!({
+        "name": program.display_name(),
+        "stream": stream,
+        "output": String::from_utf8_lossy(&data),
+    }))
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        _ => "Internal Server Error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(name: &str) -> TMHttpUser {
+        TMHttpUser {
+            name: name.to_string(),
+            password: None,
+            token: None,
+            peer: false,
+            allow: Vec::new(),
+        }
+    }
+
+    fn users() -> Vec<TMHttpUser> {
+        vec![
+            TMHttpUser {
+                password: Some("secret".to_string()),
+                allow: vec![ApiAction::Status],
+                ..user("alice")
+            },
+            // no password, only a token
+            TMHttpUser {
+                token: Some("t0ken".to_string()),
+                ..user("bob")
+            },
+            TMHttpUser {
+                peer: true,
+                ..user("root")
+            },
+        ]
+    }
+
+    fn request(method: &str, path: &str, authorization: Option<&str>) -> Request {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            authorization: authorization.map(str::to_string),
+        }
+    }
+
+    /// Name of the user the Authorization header authenticates as
+    fn authenticated(authorization: &str) -> Option<String> {
+        let request = request("GET", "/programs", Some(authorization));
+        authenticate(&request, None, &users()).map(|x| x.name.clone())
+    }
+
+    #[test]
+    fn decode_base64_padding() {
+        assert_eq!(decode_base64("YQ==").unwrap(), b"a");
+        assert_eq!(decode_base64("YWI=").unwrap(), b"ab");
+        assert_eq!(decode_base64("YWJj").unwrap(), b"abc");
+        assert_eq!(decode_base64("").unwrap(), b"");
+    }
+
+    #[test]
+    fn decode_base64_invalid() {
+        assert_eq!(decode_base64("YW!j"), None);
+        assert_eq!(decode_base64("YW J"), None);
+    }
+
+    #[test]
+    fn secure_eq_compares_whole_secrets() {
+        assert!(secure_eq("secret", "secret"));
+        assert!(!secure_eq("secret", "secreT"));
+        assert!(!secure_eq("secret", "secret2"));
+        assert!(!secure_eq("", "secret"));
+    }
+
+    #[test]
+    fn basic_auth() {
+        // alice:secret
+        assert_eq!(
+            authenticated("Basic YWxpY2U6c2VjcmV0").as_deref(),
+            Some("alice")
+        );
+        assert_eq!(
+            authenticated("basic YWxpY2U6c2VjcmV0").as_deref(),
+            Some("alice")
+        );
+        // alice:wrong
+        assert_eq!(authenticated("Basic YWxpY2U6d3Jvbmc="), None);
+        assert_eq!(authenticated("Basic not-base64!"), None);
+    }
+
+    #[test]
+    fn basic_auth_user_without_password() {
+        // bob: and bob:x
+        assert_eq!(authenticated("Basic Ym9iOg=="), None);
+        assert_eq!(authenticated("Basic Ym9iOng="), None);
+    }
+
+    #[test]
+    fn bearer_token() {
+        assert_eq!(authenticated("Bearer t0ken").as_deref(), Some("bob"));
+        assert_eq!(authenticated("Bearer t0ke"), None);
+        assert_eq!(authenticated("Bearer secret"), None);
+    }
+
+    #[test]
+    fn wrong_scheme() {
+        assert_eq!(authenticated("Digest YWxpY2U6c2VjcmV0"), None);
+        assert_eq!(authenticated("t0ken"), None);
+    }
+
+    #[test]
+    fn peer_credentials() {
+        let request = request("GET", "/programs", None);
+        let users = users();
+        let user = authenticate(&request, Some(0), &users);
+        assert_eq!(user.map(|x| x.name.as_str()), Some("root"));
+        assert!(authenticate(&request, None, &users).is_none());
+    }
+
+    #[test]
+    fn allow_list() {
+        let config = TMHttpConfig {
+            listen: "127.0.0.1:0".to_string(),
+            socket_mode: 0o600,
+            socket_owner: None,
+            socket_group: None,
+            metrics: false,
+            users: users(),
+        };
+        let programs = Mutex::new(Vec::new());
+        let status = |method, path, authorization| {
+            let request = request(method, path, authorization);
+            handle(&request, None, &config, &programs).status
+        };
+        let alice = Some("Basic YWxpY2U6c2VjcmV0");
+        // allowed, and answered with no program matching
+        assert_eq!(status("GET", "/programs", alice), 404);
+        assert_eq!(status("POST", "/programs/all/stop", alice), 403);
+        assert_eq!(status("POST", "/reload", alice), 403);
+        assert_eq!(status("POST", "/programs/all/stop", None), 401);
+        assert_eq!(status("GET", "/programs", Some("Bearer t0ken")), 404);
+    }
+}
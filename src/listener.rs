@@ -0,0 +1,98 @@
+use std::io::{BufRead, BufReader, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::process::{ChildStdin, ChildStdout};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use crate::config::TMProgramConfig;
+use crate::event::{self, Event};
+use crate::logger;
+
+/// How long to wait before sending again an event the listener answered FAIL to
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// How often to check whether the listener exited while waiting for an event
+const HANGUP_CHECK: Duration = Duration::from_secs(1);
+
+/// Talk to an event listener program until it exits.
+///
+/// The listener writes `READY\n` on its stdout once it can receive events, taskmaster then writes
+/// one event at a time on its stdin as a `EVENT <kind> <len>\n` header followed by `len` bytes of
+/// JSON, and the listener answers `OK\n` once handled or `FAIL\n` to get the event again after
+/// a delay. The answer also means it is ready for the next event.
+pub fn spawn(name: String, config: &TMProgramConfig, stdin: ChildStdin, stdout: ChildStdout) {
+    let events = event::subscribe(config.events.clone());
+    std::thread::spawn(move || {
+        if let Err(e) = serve(stdin, stdout, events) {
+            logger::error(&format!("[{name}] event listener: {e}"));
+        }
+    });
+}
+
+/// Whether the listener closed its stdout, which it only does by exiting
+fn hung_up(stdout: RawFd) -> bool {
+    let mut fd = libc::pollfd {
+        fd: stdout,
+        events: 0,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut fd, 1, 0) > 0 && fd.revents & libc::POLLHUP != 0 }
+}
+
+fn serve(
+    mut stdin: ChildStdin,
+    stdout: ChildStdout,
+    events: Receiver<Event>,
+) -> Result<(), String> {
+    let stdout_fd = stdout.as_raw_fd();
+    let mut stdout = BufReader::new(stdout);
+    let mut line = String::new();
+    let mut read_line = |line: &mut String| -> Result<(), String> {
+        line.clear();
+        match stdout.read_line(line) {
+            Ok(0) => Err("closed its stdout".to_string()),
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("failed to read: {e}")),
+        }
+    };
+    read_line(&mut line)?;
+    if line.trim_end() != "READY" {
+        return Err(format!("expected READY, got {:?}", line.trim_end()));
+    }
+    let mut failed: Option<Event> = None;
+    loop {
+        let event = match failed.take() {
+            Some(x) => {
+                std::thread::sleep(RETRY_DELAY);
+                x
+            }
+            None => loop {
+                match events.recv_timeout(HANGUP_CHECK) {
+                    Ok(x) => break x,
+                    // once exited the listener gets no more events, its receiver is dropped
+                    Err(RecvTimeoutError::Timeout) if hung_up(stdout_fd) => return Ok(()),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                }
+            },
+        };
+        let payload = event.to_json();
+        let frame = format!("EVENT {} {}\n{payload}", event.kind, payload.len());
+        if let Err(e) = stdin
+            .write_all(frame.as_bytes())
+            .and_then(|_| stdin.flush())
+        {
+            return match hung_up(stdout_fd) {
+                // it exited before the event reached it
+                true => Ok(()),
+                false => Err(format!("failed to write: {e}")),
+            };
+        }
+        read_line(&mut line)?;
+        match line.trim_end() {
+            "OK" => {}
+            "FAIL" => failed = Some(event),
+            x => return Err(format!("expected OK or FAIL, got {x:?}")),
+        }
+    }
+}
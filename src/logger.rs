@@ -0,0 +1,59 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+
+use crate::config::{TMGlobalConfig, TMSink};
+use crate::sink::{rfc5424_timestamp, Severity};
+
+struct Logger {
+    file: Option<File>,
+    sinks: Vec<TMSink>,
+}
+
+static LOGGER: Mutex<Logger> = Mutex::new(Logger {
+    file: None,
+    sinks: Vec::new(),
+});
+
+/// Open the logfile and set the sinks taskmaster events are sent to
+pub fn init(global: &TMGlobalConfig) {
+    let file = match OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&global.logfile)
+    {
+        Ok(x) => Some(x),
+        Err(e) => {
+            eprintln!("failed to open logfile {}: {e}", global.logfile);
+            None
+        }
+    };
+    *LOGGER.lock().unwrap() = Logger {
+        file,
+        sinks: global.sinks.clone(),
+    };
+}
+
+fn log(severity: Severity, msg: &str) {
+    match severity {
+        Severity::Info => println!("{msg}"),
+        Severity::Error => eprintln!("{msg}"),
+    }
+    let logger = &mut LOGGER.lock().unwrap();
+    if let Some(file) = &mut logger.file {
+        let _ = writeln!(file, "{} {msg}", rfc5424_timestamp());
+    }
+    for sink in logger.sinks.iter() {
+        if let Err(e) = sink.send("taskmaster", std::process::id(), severity, msg) {
+            eprintln!("failed to send to {sink:?}: {e}");
+        }
+    }
+}
+
+pub fn info(msg: &str) {
+    log(Severity::Info, msg)
+}
+
+pub fn error(msg: &str) {
+    log(Severity::Error, msg)
+}
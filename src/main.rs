@@ -7,74 +7,142 @@ use std::{
     },
 };
 use tokio::signal::unix::signal;
-use tokio::{
-    io::{self, stdin, AsyncBufReadExt, AsyncWriteExt},
-    signal::unix::SignalKind,
-};
+use tokio::signal::unix::SignalKind;
 
+use crate::command::CommandUser;
+use crate::config::{ConfigError, TMConfig};
+use crate::event::{Event, EventKind};
 use crate::program::TMProgram;
-use crate::{command::CommandUser, config::TMConfig};
 
+mod activation;
+mod attach;
+mod child;
 mod command;
 mod config;
+mod credentials;
+mod event;
+mod http_client;
+mod http_server;
+mod listener;
+mod logger;
+mod metrics;
+mod output;
+mod probe;
 mod program;
 mod program_state;
 mod program_status;
+mod program_usage;
+mod pty;
+mod rlimits;
+mod schedule;
 mod shell;
+mod signal;
+mod sink;
+mod state;
+mod supervisor;
+mod target;
+
+static CONFIG: LazyLock<Mutex<TMConfig>> = LazyLock::new(|| match TMConfig::load("config.toml") {
+    Ok(x) => Mutex::new(x),
+    Err(e) => panic!("{e}"),
+});
 
-static CONFIG: LazyLock<Mutex<TMConfig>> = LazyLock::new(|| {
-    let content = match std::fs::read_to_string("config.toml") {
+/// Read config.toml again and apply it: the programs whose config changed are restarted, the new
+/// ones launched and the removed ones stopped
+pub fn reload(programs: &mut Vec<TMProgram>) -> Result<(), ConfigError> {
+    let config = &mut CONFIG.lock().unwrap();
+    let new_config = match TMConfig::load("config.toml") {
+        Ok(x) => x,
+        Err(e) => {
+            metrics::RELOAD_ERRORS.fetch_add(1, Ordering::Relaxed);
+            return Err(e);
+        }
+    };
+    let mut added = match new_config.create_missing(programs) {
         Ok(x) => x,
-        Err(e) => panic!("failed to read config toml: {}", e),
+        Err(e) => {
+            metrics::RELOAD_ERRORS.fetch_add(1, Ordering::Relaxed);
+            return Err(e);
+        }
     };
-    let config = match toml::from_str::<TMConfig>(&content) {
+    let sockets = match new_config.changed_sockets(programs) {
         Ok(x) => x,
-        Err(e) => panic!("failed to parse content to toml: {}", e),
+        Err(e) => {
+            metrics::RELOAD_ERRORS.fetch_add(1, Ordering::Relaxed);
+            return Err(e);
+        }
     };
-    Mutex::new(config)
-});
+    logger::init(&new_config.global);
+    state::set_path(new_config.global.state_file.clone());
+    // the restart of the programs whose listen changed uses the new sockets
+    for program in programs.iter_mut().chain(added.iter_mut()) {
+        if let Some(x) = sockets.get(&program.name) {
+            program.sockets = x.clone();
+        }
+    }
+    for program in programs.iter_mut() {
+        match new_config.programs.get(&program.name) {
+            Some(x) if program.instance < x.process => {
+                if program.removed {
+                    supervisor::restore(program);
+                }
+                program.groups = new_config.groups_of(&program.name);
+                supervisor::apply_config(program, x);
+            }
+            _ if program.removed => {}
+            _ => supervisor::remove(program),
+        }
+    }
+    for program in added.iter_mut() {
+        logger::info(&format!("[{}] added to config", program.display_name()));
+        if let Err(e) = program.autostart() {
+            logger::error(&format!(
+                "[{}] failed to launch: {e}",
+                program.display_name()
+            ));
+        }
+    }
+    programs.append(&mut added);
+    config.programs = new_config.programs;
+    config.groups = new_config.groups;
+    config.hooks = new_config.hooks;
+    metrics::RELOADS.fetch_add(1, Ordering::Relaxed);
+    event::emit(Event::new(EventKind::Reloaded));
+    Ok(())
+}
 
-async fn handle_sighup() {
+async fn handle_sighup(programs: Arc<Mutex<Vec<TMProgram>>>) {
     let mut stream = signal(SignalKind::hangup()).expect("Failed to create stream for SIGHUP");
     loop {
         stream.recv().await;
-        println!("SIGHUP received");
-        let programs = &mut CONFIG.lock().unwrap().programs;
-        let content = match std::fs::read_to_string("config.toml") {
-            Ok(x) => x,
-            Err(e) => {
-                eprintln!("failed to read config toml: {}", e);
-                return;
-            }
-        };
-        let new_config = match toml::from_str::<TMConfig>(&content) {
-            Ok(x) => x,
-            Err(e) => {
-                eprintln!("failed to parse content to toml: {}", e);
-                return;
-            }
-        };
-
-        programs.iter_mut().for_each(|it| {
-            let new_program_config = match new_config.programs.get(it.0) {
-                Some(x) => x.clone(),
-                None => todo!("launch new program after config reload"),
-            };
-            *it.1 = new_program_config;
-        });
+        logger::info("SIGHUP received");
+        if let Err(e) = reload(&mut programs.lock().unwrap()) {
+            logger::error(&format!("{e}"));
+        }
     }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    tokio::spawn(handle_sighup());
-
+    logger::init(&CONFIG.lock()?.global);
+    event::start();
     let running_arc = Arc::new(AtomicBool::new(true));
     let programs_arc = Arc::new(Mutex::new(Vec::new()));
+    tokio::spawn(handle_sighup(programs_arc.clone()));
+    let state_file = CONFIG.lock()?.global.state_file.clone();
+    let saved = match &state_file {
+        Some(path) => state::load(path),
+        None => Vec::new(),
+    };
+    state::set_path(state_file);
     programs_arc
         .lock()
         .unwrap()
-        .append(&mut CONFIG.lock()?.launch_all()?);
+        .append(&mut CONFIG.lock()?.launch_all(&saved)?);
+    tokio::spawn(supervisor::supervise(programs_arc.clone()));
+    if let Some(http) = CONFIG.lock()?.global.http.clone() {
+        tokio::spawn(http_server::serve(http, programs_arc.clone()));
+    }
     let programs = programs_arc.clone();
     let running = running_arc.clone();
 
@@ -82,25 +150,19 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     while running.load(Ordering::SeqCst) {
         let user_input = shell.read_line().await?;
-        let mut user_input = user_input.split_whitespace();
-        let cmd = match user_input.next() {
-            Some(x) => x,
-            None => continue,
-        };
-        //convert 2nd argument to u32
-        let val = match user_input.next() {
-            None => None,
-            Some(x) => match x.parse() {
-                Err(e) => {
-                    eprintln!("Failed to parse argument to u32: {e}");
-                    continue;
-                }
-                Ok(x) => Some(x),
-            },
-        };
-        match CommandUser::try_from((cmd, val)) {
+        let args: Vec<&str> = user_input.split_whitespace().collect();
+        if args.is_empty() {
+            continue;
+        }
+        match CommandUser::try_from(args.as_slice()) {
             Ok(cmd) => {
-                if let Err(e) = cmd.exec(&mut programs.lock().unwrap(), running.clone()) {
+                let result = match &cmd {
+                    CommandUser::Attach(target) => {
+                        attach::attach(&programs, target, &mut shell).await
+                    }
+                    _ => cmd.exec(&mut programs.lock().unwrap(), running.clone()),
+                };
+                if let Err(e) = result {
                     match e {
                         command::CommandError::ProgramNotLaunched => {
                             eprintln!("Program not launched")
@@ -110,6 +172,19 @@ async fn main() -> Result<(), Box<dyn Error>> {
                         command::CommandError::MissingParams => {
                             eprintln!("Missings parametes for command")
                         }
+                        command::CommandError::UnknownSignal => eprintln!("Unknown signal"),
+                        command::CommandError::UnknownTarget => {
+                            eprintln!("No program matches the target")
+                        }
+                        command::CommandError::RolloutInProgress => {
+                            eprintln!("A rolling restart is already in progress")
+                        }
+                        command::CommandError::AmbiguousTarget => {
+                            eprintln!("The target matches several instances")
+                        }
+                        command::CommandError::NotAttachable => {
+                            eprintln!("Program is not attachable")
+                        }
                         command::CommandError::RuntimeError => {
                             eprintln!("Unknown RuntineError")
                         }
@@ -120,12 +195,6 @@ async fn main() -> Result<(), Box<dyn Error>> {
         };
     }
     running.store(false, Ordering::SeqCst);
-    programs.lock().unwrap().iter_mut().for_each(|x| {
-        if let Some(child) = &mut x.child {
-            if let Err(e) = &child.kill() {
-                eprintln!("failed to kill {x:?}: {e}");
-            }
-        }
-    });
+    supervisor::shutdown(&mut programs.lock().unwrap());
     Ok(())
 }
@@ -0,0 +1,202 @@
+use std::fmt::Write;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::program::TMProgram;
+use crate::signal::signal_name;
+
+/// How many times the config was reloaded
+pub static RELOADS: AtomicU64 = AtomicU64::new(0);
+/// How many reloads failed, leaving the previous config in place
+pub static RELOAD_ERRORS: AtomicU64 = AtomicU64::new(0);
+
+/// Every value of TMProgram::state_label
+const STATES: [&str; 8] = [
+    "RUNNING",
+    "EXITED",
+    "COMPLETED",
+    "BACKOFF",
+    "WAITING",
+    "STOPPED",
+    "FATAL",
+    "UNKNOWN",
+];
+
+impl TMProgram {
+    pub fn record_exit(&mut self, status: ExitStatus) {
+        match (status.code(), status.signal()) {
+            (Some(code), _) => *self.exit_codes.entry(code).or_default() += 1,
+            (None, Some(signal)) => *self.exit_signals.entry(signal).or_default() += 1,
+            (None, None) => {}
+        }
+    }
+}
+
+/// Metrics in the Prometheus text format
+pub fn render(programs: &mut [TMProgram]) -> String {
+    let mut out = String::new();
+    header(
+        &mut out,
+        "taskmaster_reloads_total",
+        "counter",
+        "Successful config reloads",
+    );
+    let _ = writeln!(
+        out,
+        "taskmaster_reloads_total {}",
+        RELOADS.load(Ordering::Relaxed)
+    );
+    header(
+        &mut out,
+        "taskmaster_reload_errors_total",
+        "counter",
+        "Failed config reloads",
+    );
+    let _ = writeln!(
+        out,
+        "taskmaster_reload_errors_total {}",
+        RELOAD_ERRORS.load(Ordering::Relaxed)
+    );
+
+    header(
+        &mut out,
+        "taskmaster_program_state",
+        "gauge",
+        "1 for the current state of the program",
+    );
+    for program in programs.iter_mut() {
+        let state = program.state_label();
+        for x in STATES {
+            let _ = writeln!(
+                out,
+                "taskmaster_program_state{{{},state=\"{x}\"}} {}",
+                labels(program),
+                (x == state) as u8
+            );
+        }
+    }
+
+    header(
+        &mut out,
+        "taskmaster_program_uptime_seconds",
+        "gauge",
+        "Time since the program was launched, 0 when it is not running",
+    );
+    for program in programs.iter_mut() {
+        let uptime = match (program.is_running(), program.started_at) {
+            (true, Some(x)) => x.elapsed().as_secs_f64(),
+            _ => 0.0,
+        };
+        let _ = writeln!(
+            out,
+            "taskmaster_program_uptime_seconds{{{}}} {uptime:.3}",
+            labels(program)
+        );
+    }
+
+    header(
+        &mut out,
+        "taskmaster_program_restarts_total",
+        "counter",
+        "Restarts of the program by the supervisor, after an exit or a failed watchdog or probe",
+    );
+    for program in programs.iter() {
+        let _ = writeln!(
+            out,
+            "taskmaster_program_restarts_total{{{}}} {}",
+            labels(program),
+            program.restarts_total
+        );
+    }
+
+    header(
+        &mut out,
+        "taskmaster_program_exits_total",
+        "counter",
+        "Exits of the program by exit code",
+    );
+    for program in programs.iter() {
+        for (code, count) in program.exit_codes.iter() {
+            let _ = writeln!(
+                out,
+                "taskmaster_program_exits_total{{{},code=\"{code}\"}} {count}",
+                labels(program)
+            );
+        }
+    }
+
+    header(
+        &mut out,
+        "taskmaster_program_signal_exits_total",
+        "counter",
+        "Exits of the program by killing signal",
+    );
+    for program in programs.iter() {
+        for (signal, count) in program.exit_signals.iter() {
+            let _ = writeln!(
+                out,
+                "taskmaster_program_signal_exits_total{{{},signal=\"{}\"}} {count}",
+                labels(program),
+                signal_name(*signal)
+            );
+        }
+    }
+
+    let mut usages = Vec::new();
+    for program in programs.iter_mut() {
+        if program.is_running() {
+            if let Ok(usage) = program.usage() {
+                usages.push((labels(program), usage));
+            }
+        }
+    }
+    header(
+        &mut out,
+        "taskmaster_program_rss_bytes",
+        "gauge",
+        "Resident memory of the running program",
+    );
+    for (labels, usage) in usages.iter() {
+        let _ = writeln!(
+            out,
+            "taskmaster_program_rss_bytes{{{labels}}} {}",
+            usage.rss
+        );
+    }
+    header(
+        &mut out,
+        "taskmaster_program_cpu_seconds_total",
+        "counter",
+        "CPU time used by the running program",
+    );
+    for (labels, usage) in usages.iter() {
+        let _ = writeln!(
+            out,
+            "taskmaster_program_cpu_seconds_total{{{labels}}} {:.3}",
+            usage.cpu_time.as_secs_f64()
+        );
+    }
+    out
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn labels(program: &TMProgram) -> String {
+    format!(
+        "program=\"{}\",instance=\"{}\"",
+        escape(&program.name),
+        program.instance
+    )
+}
+
+/// Escape a label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
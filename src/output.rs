@@ -0,0 +1,303 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::config::TMSink;
+use crate::sink::Severity;
+
+/// File receiving the output of a program, rotated once it grows over maxbytes
+#[derive(Debug)]
+pub struct LogFile {
+    path: String,
+    file: Option<File>,
+    size: u64,
+    /// Rotate the file once it would grow over this size, 0 to never rotate
+    maxbytes: u64,
+    /// How many rotated files to keep
+    backups: u32,
+    /// Gzip the rotated files
+    compress: bool,
+}
+
+impl LogFile {
+    pub fn open(path: &str, maxbytes: u64, backups: u32, compress: bool) -> io::Result<Self> {
+        let mut log = Self {
+            path: path.to_string(),
+            file: None,
+            size: 0,
+            maxbytes,
+            backups,
+            compress,
+        };
+        log.reopen()?;
+        Ok(log)
+    }
+
+    /// Close and open the file again, for when it was moved by an external logrotate
+    pub fn reopen(&mut self) -> io::Result<()> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let metadata = file.metadata()?;
+        // only regular files can be rotated, /dev/stdout cannot
+        if !metadata.is_file() {
+            self.maxbytes = 0;
+        }
+        self.size = metadata.len();
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn backup_path(&self, idx: u32) -> String {
+        match self.compress {
+            true => format!("{}.{idx}.gz", self.path),
+            false => format!("{}.{idx}", self.path),
+        }
+    }
+
+    /// Shift the backups (path.1 -> path.2 ...), move the file to path.1 and start a new one
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+        if self.backups == 0 {
+            fs::remove_file(&self.path)?;
+            return self.reopen();
+        }
+        for idx in (1..self.backups).rev() {
+            let from = self.backup_path(idx);
+            if fs::exists(&from)? {
+                fs::rename(&from, self.backup_path(idx + 1))?;
+            }
+        }
+        let rotated = format!("{}.1", self.path);
+        fs::rename(&self.path, &rotated)?;
+        self.reopen()?;
+        if self.compress {
+            let mut encoder =
+                GzEncoder::new(File::create(self.backup_path(1))?, Compression::default());
+            io::copy(&mut File::open(&rotated)?, &mut encoder)?;
+            encoder.finish()?;
+            fs::remove_file(&rotated)?;
+        }
+        Ok(())
+    }
+
+    pub fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        if self.maxbytes > 0 && self.size > 0 && self.size + buf.len() as u64 > self.maxbytes {
+            self.rotate()?;
+        }
+        let file = match &mut self.file {
+            None => return Err(io::Error::from(io::ErrorKind::NotFound)),
+            Some(x) => x,
+        };
+        file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        Ok(())
+    }
+}
+
+/// How much of the last output of a program is kept in memory
+const TAIL_SIZE: usize = 64 * 1024;
+
+/// Last bytes written by a program on one of its outputs
+#[derive(Debug, Default)]
+pub struct OutputTail {
+    buf: VecDeque<u8>,
+}
+
+impl OutputTail {
+    pub fn push(&mut self, data: &[u8]) {
+        self.buf.extend(data);
+        if self.buf.len() > TAIL_SIZE {
+            self.buf.drain(..self.buf.len() - TAIL_SIZE);
+        }
+    }
+
+    /// Last len bytes of output
+    pub fn last(&self, len: usize) -> Vec<u8> {
+        self.buf
+            .iter()
+            .skip(self.buf.len().saturating_sub(len))
+            .copied()
+            .collect()
+    }
+}
+
+/// Where one output stream of a program goes
+pub struct OutputTarget {
+    pub log: Option<Arc<Mutex<LogFile>>>,
+    pub tail: Arc<Mutex<OutputTail>>,
+    pub sinks: Vec<TMSink>,
+    /// Name and pid the lines are sent to the sinks with
+    pub identifier: String,
+    pub pid: u32,
+    pub severity: Severity,
+    /// Whether the terminal is attached to the program, the output is then printed too
+    pub attached: Arc<AtomicBool>,
+}
+
+impl OutputTarget {
+    fn send_line(&self, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        for sink in self.sinks.iter() {
+            if let Err(e) = sink.send(&self.identifier, self.pid, self.severity, &line) {
+                eprintln!("[{}] failed to send to {sink:?}: {e}", self.identifier);
+            }
+        }
+    }
+}
+
+/// Read the output of a program until it closes it, writing it to the log file and sending each
+/// line to the sinks
+pub fn spawn_drain(mut output: impl Read + Send + 'static, target: OutputTarget) {
+    std::thread::spawn(move || {
+        let mut buf = [0; 4096];
+        let mut line: Vec<u8> = Vec::new();
+        loop {
+            let len = match output.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(x) => x,
+            };
+            target.tail.lock().unwrap().push(&buf[..len]);
+            if target.attached.load(Ordering::Relaxed) {
+                let mut stdout = std::io::stdout().lock();
+                let _ = stdout.write_all(&buf[..len]);
+                let _ = stdout.flush();
+            }
+            if let Some(log) = &target.log {
+                let log = &mut log.lock().unwrap();
+                if let Err(e) = log.write(&buf[..len]) {
+                    eprintln!("failed to write to {}: {e}", log.path);
+                }
+            }
+            if target.sinks.is_empty() {
+                continue;
+            }
+            for byte in buf[..len].iter() {
+                match byte {
+                    b'\n' => target.send_line(&std::mem::take(&mut line)),
+                    x => line.push(*x),
+                }
+            }
+        }
+        if !line.is_empty() {
+            target.send_line(&line);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(path: &str) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    /// Log file called "log" in a new temporary directory
+    fn log_file(
+        maxbytes: u64,
+        backups: u32,
+        compress: bool,
+    ) -> (tempfile::TempDir, String, LogFile) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log").to_str().unwrap().to_string();
+        let log = LogFile::open(&path, maxbytes, backups, compress).unwrap();
+        (dir, path, log)
+    }
+
+    #[test]
+    fn rotate_once_over_maxbytes() {
+        let (_dir, path, mut log) = log_file(10, 3, false);
+        log.write(b"12345").unwrap();
+        log.write(b"67890").unwrap();
+        assert_eq!(read(&path), "1234567890");
+        log.write(b"x").unwrap();
+        assert_eq!(read(&path), "x");
+        assert_eq!(read(&format!("{path}.1")), "1234567890");
+    }
+
+    #[test]
+    fn write_larger_than_maxbytes_in_empty_file() {
+        let (_dir, path, mut log) = log_file(4, 3, false);
+        log.write(b"123456").unwrap();
+        assert_eq!(read(&path), "123456");
+        assert!(!fs::exists(format!("{path}.1")).unwrap());
+    }
+
+    #[test]
+    fn rotate_shifts_backups() {
+        let (_dir, path, mut log) = log_file(1, 2, false);
+        for data in ["a", "b", "c", "d"] {
+            log.write(data.as_bytes()).unwrap();
+        }
+        assert_eq!(read(&path), "d");
+        assert_eq!(read(&format!("{path}.1")), "c");
+        assert_eq!(read(&format!("{path}.2")), "b");
+        assert!(!fs::exists(format!("{path}.3")).unwrap());
+    }
+
+    #[test]
+    fn rotate_without_backups() {
+        let (dir, path, mut log) = log_file(1, 0, false);
+        log.write(b"a").unwrap();
+        log.write(b"b").unwrap();
+        assert_eq!(read(&path), "b");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn rotate_compressed() {
+        let (_dir, path, mut log) = log_file(5, 2, true);
+        for data in ["hello", "world", "again"] {
+            log.write(data.as_bytes()).unwrap();
+        }
+        let gunzip = |path: String| {
+            let mut content = String::new();
+            flate2::read::GzDecoder::new(File::open(path).unwrap())
+                .read_to_string(&mut content)
+                .unwrap();
+            content
+        };
+        assert_eq!(read(&path), "again");
+        assert_eq!(gunzip(format!("{path}.1.gz")), "world");
+        assert_eq!(gunzip(format!("{path}.2.gz")), "hello");
+        assert!(!fs::exists(format!("{path}.1")).unwrap());
+    }
+
+    #[test]
+    fn reopen_keeps_the_size() {
+        let (_dir, path, mut log) = log_file(10, 1, false);
+        log.write(b"12345678").unwrap();
+        log.reopen().unwrap();
+        log.write(b"90").unwrap();
+        log.write(b"x").unwrap();
+        assert_eq!(read(&format!("{path}.1")), "1234567890");
+    }
+
+    #[test]
+    fn reopen_non_regular_file_never_rotates() {
+        let mut log = LogFile::open("/dev/null", 1, 1, false).unwrap();
+        assert_eq!(log.maxbytes, 0);
+        log.write(b"ab").unwrap();
+        log.write(b"cd").unwrap();
+        assert!(!fs::exists("/dev/null.1").unwrap());
+    }
+
+    #[test]
+    fn tail_keeps_the_last_bytes() {
+        let mut tail = OutputTail::default();
+        tail.push(b"hello ");
+        tail.push(b"world");
+        assert_eq!(tail.last(5), b"world");
+        assert_eq!(tail.last(100), b"hello world");
+        tail.push(&vec![b'x'; TAIL_SIZE]);
+        assert_eq!(tail.buf.len(), TAIL_SIZE);
+        assert_eq!(tail.last(1), b"x");
+    }
+}
@@ -0,0 +1,92 @@
+use std::time::{Duration, Instant};
+
+use tokio::net::TcpStream;
+use tokio::process::Command;
+use tokio::sync::oneshot::{self, error::TryRecvError};
+
+use crate::config::{ProbeCheck, TMProbe};
+use crate::http_client;
+
+/// Result of the probes of a program, updated by the supervisor
+#[derive(Debug, Default)]
+pub struct ProbeState {
+    /// How many probes failed in a row
+    pub failures: u32,
+    /// Whether the last probe succeeded
+    pub success: bool,
+    last_run: Option<Instant>,
+    pending: Option<oneshot::Receiver<bool>>,
+}
+
+impl ProbeState {
+    /// Collect the result of the running probe and start a new one when it is due, return true
+    /// when a result was collected
+    pub fn poll(&mut self, probe: &TMProbe) -> bool {
+        if let Some(pending) = &mut self.pending {
+            let success = match pending.try_recv() {
+                Err(TryRecvError::Empty) => return false,
+                Err(TryRecvError::Closed) => false,
+                Ok(x) => x,
+            };
+            self.pending = None;
+            self.success = success;
+            self.failures = if success { 0 } else { self.failures + 1 };
+            return true;
+        }
+        let interval = Duration::from_secs(probe.interval.into());
+        if self.last_run.is_some_and(|x| x.elapsed() < interval) {
+            return false;
+        }
+        let (tx, rx) = oneshot::channel();
+        let check = probe.check.clone();
+        let timeout = Duration::from_secs(probe.timeout.into());
+        tokio::spawn(async move {
+            let success = tokio::time::timeout(timeout, check.run())
+                .await
+                .unwrap_or(false);
+            let _ = tx.send(success);
+        });
+        self.last_run = Some(Instant::now());
+        self.pending = Some(rx);
+        false
+    }
+
+    pub fn is_failing(&self, probe: &TMProbe) -> bool {
+        self.failures >= probe.failure_threshold
+    }
+}
+
+impl ProbeCheck {
+    pub async fn run(self) -> bool {
+        match self {
+            Self::Exec(command) => exec(command).await,
+            Self::Tcp(address) => TcpStream::connect(local_address(&address)).await.is_ok(),
+            Self::Http(url) => http_client::request("GET", &url, None)
+                .await
+                .is_some_and(|x| (200..400).contains(&x)),
+        }
+    }
+}
+
+async fn exec(command: Vec<String>) -> bool {
+    let Some((program, args)) = command.split_first() else {
+        return false;
+    };
+    Command::new(program)
+        .args(args)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .status()
+        .await
+        .is_ok_and(|x| x.success())
+}
+
+/// A bare port means a port of localhost
+fn local_address(address: &str) -> String {
+    match address.parse::<u16>() {
+        Ok(port) => format!("127.0.0.1:{port}"),
+        Err(_) => address.to_string(),
+    }
+}
@@ -1,30 +1,370 @@
-use std::fs::File;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
 use std::io;
+use std::os::fd::OwnedFd;
+use std::os::unix::process::CommandExt;
 use std::process::{Child, Command, Stdio};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use crate::config::TMProgramConfig;
+use crate::activation::Activation;
+use crate::child::TMChild;
+use crate::config::{ProgramType, TMProgramConfig};
+use crate::credentials::Credentials;
+use crate::event::{self, EventKind};
+use crate::listener;
+use crate::output::{spawn_drain, LogFile, OutputTail, OutputTarget};
+use crate::probe::ProbeState;
+use crate::pty;
+use crate::schedule::ScheduleState;
+use crate::signal::parse_signal;
+use crate::sink::Severity;
 
 #[derive(Debug)]
 pub struct TMProgram {
+    pub name: String,
+    /// Which of the `process` instances of the program this is
+    pub instance: u32,
+    /// Groups the program belongs to
+    pub groups: Vec<String>,
     pub config: TMProgramConfig,
-    pub child: Option<Child>,
+    pub child: Option<TMChild>,
+    /// Files receiving the output of the program, kept open across restarts
+    pub stdout_log: Option<Arc<Mutex<LogFile>>>,
+    pub stderr_log: Option<Arc<Mutex<LogFile>>>,
+    /// Last output of the program, kept across restarts
+    pub stdout_tail: Arc<Mutex<OutputTail>>,
+    pub stderr_tail: Arc<Mutex<OutputTail>>,
+    /// When the program was last launched
+    pub started_at: Option<Instant>,
+    /// Launch the program once every program it depends on is healthy
+    pub waiting_dependencies: bool,
+    /// Whether the supervisor already handled the exit of the child
+    pub exit_handled: bool,
+    /// Restart attempts since the program was last healthy
+    pub restarts: u32,
+    /// Restarts by the supervisor, after an exit or a failed watchdog or probe, never reset
+    pub restarts_total: u64,
+    /// When to launch the program again after an unexpected exit
+    pub backoff_until: Option<Instant>,
+    /// The supervisor gave up restarting the program
+    pub fatal: bool,
+    /// The oneshot program exited with an expected status
+    pub completed: bool,
+    /// Stopped with the kill command, nothing but the launch command starts it again
+    pub stopped_by_user: bool,
+    /// No longer in the config, dropped by the supervisor once it is stopped
+    pub removed: bool,
+    /// When to kill the program if it still runs after receiving its stopsignal
+    pub stop_deadline: Option<Instant>,
+    /// Launch the program again once the graceful stop is over
+    pub restart_after_stop: bool,
+    pub readiness: ProbeState,
+    pub liveness: ProbeState,
+    /// Last CPU time sampled by the supervisor
+    pub last_sample: Option<(Instant, Duration)>,
+    /// Since when the CPU usage is over the watchdog threshold
+    pub cpu_over_since: Option<Instant>,
+    /// How many times the program was launched since taskmaster started
+    pub launches: u64,
+    /// How many times the program exited with each exit code
+    pub exit_codes: BTreeMap<i32, u64>,
+    /// How many times the program was killed by each signal
+    pub exit_signals: BTreeMap<i32, u64>,
+    pub scheduled: ScheduleState,
+    /// Listening sockets of the program, kept open across restarts
+    pub sockets: Arc<Vec<OwnedFd>>,
+    /// stdin pipe or pty master of the program, replaced on each launch
+    pub stdin: Arc<Mutex<Option<File>>>,
+    /// Whether the terminal is attached to the program
+    pub attached: Arc<AtomicBool>,
 }
 
 impl TMProgram {
+    pub fn new(name: String, instance: u32, config: TMProgramConfig) -> Self {
+        Self {
+            name,
+            instance,
+            groups: Vec::new(),
+            config,
+            child: None,
+            stdout_log: None,
+            stderr_log: None,
+            stdout_tail: Arc::default(),
+            stderr_tail: Arc::default(),
+            started_at: None,
+            waiting_dependencies: false,
+            exit_handled: false,
+            restarts: 0,
+            restarts_total: 0,
+            backoff_until: None,
+            fatal: false,
+            completed: false,
+            stopped_by_user: false,
+            removed: false,
+            stop_deadline: None,
+            restart_after_stop: false,
+            readiness: ProbeState::default(),
+            liveness: ProbeState::default(),
+            last_sample: None,
+            cpu_over_since: None,
+            launches: 0,
+            exit_codes: BTreeMap::new(),
+            exit_signals: BTreeMap::new(),
+            scheduled: ScheduleState::default(),
+            sockets: Arc::default(),
+            stdin: Arc::default(),
+            attached: Arc::default(),
+        }
+    }
+
+    /// Name of the instance, suffixed by its number when the program has several instances
+    pub fn display_name(&self) -> String {
+        match self.config.process {
+            0 | 1 => self.name.clone(),
+            _ => format!("{}:{}", self.name, self.instance),
+        }
+    }
+
     pub fn launch(&mut self) -> io::Result<()> {
-        match Command::new(&self.config.command)
-            .args(&self.config.args)
-            .stdout(match &self.config.stdout {
-                None => Stdio::piped(),
-                Some(x) => Stdio::from(File::open(x)?),
-            })
-            .spawn()
-        {
-            Ok(x) => {
-                self.child = Some(x);
+        let rlimits = self.config.rlimits.clone();
+        let credentials = Credentials::resolve(&self.config)?;
+        let pty = match self.config.pty {
+            true => Some(pty::open(&self.config.pty_size)?),
+            false => None,
+        };
+        let is_pty = pty.is_some();
+        let mut command = Command::new(&self.config.command);
+        if !self.config.direct_output {
+            self.open_logs()?;
+        }
+        command.args(&self.config.args);
+        match &pty {
+            // the new session of the pty is a new process group too
+            Some((_, slave)) => command
+                .stdin(Stdio::from(slave.try_clone()?))
+                .stdout(Stdio::from(slave.try_clone()?))
+                .stderr(Stdio::from(slave.try_clone()?)),
+            None => command
+                .process_group(0)
+                .stdin(match (&self.config.program_type, &self.config.stdin) {
+                    (ProgramType::EventListener, _) => Stdio::piped(),
+                    _ if self.config.attachable => Stdio::piped(),
+                    (_, Some(path)) => Stdio::from(File::open(path)?),
+                    (_, None) => Stdio::null(),
+                })
+                .stdout(self.output_stdio(&self.config.stdout)?)
+                .stderr(self.output_stdio(&self.config.stderr)?),
+        };
+        // set up last, the exec of the activation needs the whole command
+        let mut activation = match self.sockets.is_empty() {
+            true => None,
+            false => Some(Activation::new(&self.sockets, &mut command)?),
+        };
+        unsafe {
+            command.pre_exec(move || {
+                if is_pty {
+                    pty::make_controlling()?;
+                }
+                rlimits.apply()?;
+                if let Some(x) = &credentials {
+                    x.apply()?;
+                }
+                match &mut activation {
+                    None => Ok(()),
+                    Some(x) => Err(x.exec()),
+                }
+            });
+        }
+        let spawned = command.spawn();
+        // the child holds the only copies of the slave left
+        drop(command);
+        match spawned {
+            Ok(mut x) => {
+                match (x.stdin.take(), &self.config.program_type) {
+                    (Some(stdin), ProgramType::EventListener) => {
+                        if let Some(stdout) = x.stdout.take() {
+                            listener::spawn(self.display_name(), &self.config, stdin, stdout);
+                        }
+                    }
+                    (stdin, _) => {
+                        *self.stdin.lock().unwrap() = stdin.map(|x| File::from(OwnedFd::from(x)))
+                    }
+                }
+                if let Some((master, _)) = pty {
+                    let output = File::from(master.try_clone()?);
+                    spawn_drain(output, self.output_target(&x, Severity::Info));
+                    *self.stdin.lock().unwrap() = Some(File::from(master));
+                }
+                if let Some(stdout) = x.stdout.take() {
+                    spawn_drain(stdout, self.output_target(&x, Severity::Info));
+                }
+                if let Some(stderr) = x.stderr.take() {
+                    spawn_drain(stderr, self.output_target(&x, Severity::Error));
+                }
+                self.child = Some(TMChild::Spawned(x));
+                self.started_at = Some(Instant::now());
+                self.waiting_dependencies = false;
+                self.exit_handled = false;
+                self.backoff_until = None;
+                self.fatal = false;
+                self.completed = false;
+                self.readiness = ProbeState::default();
+                self.liveness = ProbeState::default();
+                self.last_sample = None;
+                self.cpu_over_since = None;
+                self.launches += 1;
+                self.scheduled.start();
+                event::emit(self.event(EventKind::ProcessStarted));
                 Ok(())
             }
             Err(e) => Err(e),
         }
     }
+
+    /// Where the program writes an output stream: its file when it writes it directly, a pipe
+    /// drained by taskmaster otherwise
+    fn output_stdio(&self, path: &Option<String>) -> io::Result<Stdio> {
+        match (self.config.direct_output, path) {
+            (false, _) => Ok(Stdio::piped()),
+            (true, None) => Ok(Stdio::null()),
+            (true, Some(path)) => Ok(OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?
+                .into()),
+        }
+    }
+
+    fn output_target(&self, child: &Child, severity: Severity) -> OutputTarget {
+        OutputTarget {
+            log: match severity {
+                Severity::Info => self.stdout_log.clone(),
+                Severity::Error => self.stderr_log.clone(),
+            },
+            tail: match severity {
+                Severity::Info => self.stdout_tail.clone(),
+                Severity::Error => self.stderr_tail.clone(),
+            },
+            sinks: self.config.sinks.clone().unwrap_or_default(),
+            identifier: self.display_name(),
+            pid: child.id(),
+            severity,
+            attached: self.attached.clone(),
+        }
+    }
+
+    /// Open the log files on the first launch
+    fn open_logs(&mut self) -> io::Result<()> {
+        let config = &self.config;
+        if let (None, Some(path)) = (&self.stdout_log, &config.stdout) {
+            let log = LogFile::open(
+                path,
+                config.stdout_logfile_maxbytes,
+                config.stdout_logfile_backups,
+                config.stdout_logfile_compress,
+            )?;
+            self.stdout_log = Some(Arc::new(Mutex::new(log)));
+        }
+        if let (None, Some(path)) = (&self.stderr_log, &config.stderr) {
+            let log = LogFile::open(
+                path,
+                config.stderr_logfile_maxbytes,
+                config.stderr_logfile_backups,
+                config.stderr_logfile_compress,
+            )?;
+            self.stderr_log = Some(Arc::new(Mutex::new(log)));
+        }
+        Ok(())
+    }
+
+    /// Reopen the log files, after an external logrotate moved them
+    pub fn reopen_logs(&self) -> io::Result<()> {
+        for log in [&self.stdout_log, &self.stderr_log].into_iter().flatten() {
+            log.lock().unwrap().reopen()?;
+        }
+        Ok(())
+    }
+
+    /// Launch the program if it is autostart, right away when it has no dependency or once the
+    /// supervisor sees its dependencies healthy, a scheduled program is only launched by its
+    /// schedule
+    pub fn autostart(&mut self) -> io::Result<()> {
+        if self.config.schedule.is_some() {
+            return Ok(());
+        }
+        if self.config.autostart && self.config.depends_on.is_empty() {
+            self.launch()?;
+        } else if self.config.autostart {
+            self.waiting_dependencies = true;
+        }
+        Ok(())
+    }
+
+    pub fn is_running(&mut self) -> bool {
+        match &mut self.child {
+            None => false,
+            Some(x) => matches!(x.try_wait(), Ok(None)),
+        }
+    }
+
+    /// Whether the program is running for health_time, and its readiness probe succeeds
+    pub fn is_healthy(&mut self) -> bool {
+        let health_time = Duration::from_secs(self.config.health_time.into());
+        self.is_running()
+            && self.started_at.is_some_and(|x| x.elapsed() >= health_time)
+            && (self.config.readiness.is_none() || self.readiness.success)
+    }
+
+    /// Whether the programs depending on this one can be started
+    pub fn satisfies_dependents(&mut self) -> bool {
+        match self.config.program_type {
+            ProgramType::Oneshot => self.completed,
+            _ => self.is_healthy(),
+        }
+    }
+
+    /// Send the stopsignal to the program, the supervisor kills it if it is still running after
+    /// graceful_period
+    pub fn stop(&mut self) -> io::Result<()> {
+        let signal = match parse_signal(&self.config.stopsignal) {
+            None => return Err(io::Error::from(io::ErrorKind::InvalidInput)),
+            Some(x) => x,
+        };
+        self.send_signal(signal, self.config.stopasgroup)?;
+        self.stop_deadline =
+            Some(Instant::now() + Duration::from_secs(self.config.graceful_period.into()));
+        Ok(())
+    }
+
+    /// Kill the program right away with SIGKILL
+    pub fn kill(&mut self) -> io::Result<()> {
+        if self.config.killasgroup {
+            return self.send_signal(libc::SIGKILL, true);
+        }
+        match &mut self.child {
+            None => Err(io::Error::from(io::ErrorKind::NotFound)),
+            Some(x) => x.kill(),
+        }
+    }
+
+    /// Send a signal to the program only
+    pub fn signal(&self, signal: libc::c_int) -> io::Result<()> {
+        self.send_signal(signal, false)
+    }
+
+    /// Send a signal to the program, or to its whole process group
+    fn send_signal(&self, signal: libc::c_int, group: bool) -> io::Result<()> {
+        let pid = match &self.child {
+            None => return Err(io::Error::from(io::ErrorKind::NotFound)),
+            Some(x) => x.id() as libc::pid_t,
+        };
+        // each program leads its own process group, so its pgid is its pid
+        let target = if group { -pid } else { pid };
+        if unsafe { libc::kill(target, signal) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
 }
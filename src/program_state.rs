@@ -49,19 +49,24 @@ impl TryFrom<u32> for ProgramState {
             2 => Ok(Self::Running),
             3 => Ok(Self::Sleeping),
             4 => Ok(Self::Zombie),
-            _ => Err(StateError::UnknownState),
+            _ => Err(StateError::UnknownState(value.to_string())),
         }
     }
 }
 
 #[derive(Debug)]
 pub enum StateError {
-    UnknownState,
+    UnknownState(String),
     ProgramNotLaunched,
+    RuntimeError(Box<dyn std::error::Error>),
 }
 impl Display for StateError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            Self::UnknownState(state) => write!(f, "unknown state: {state}"),
+            Self::ProgramNotLaunched => write!(f, "program not launched"),
+            Self::RuntimeError(e) => write!(f, "runtime error: {e}"),
+        }
     }
 }
 
@@ -1,6 +1,7 @@
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 use std::os::unix::prelude::ExitStatusExt;
+use std::time::{Duration, Instant};
 
 use crate::program::TMProgram;
 use crate::program_state::{ProgramState, StateError};
@@ -10,6 +11,7 @@ pub enum ProgramStatus {
     Code(i32),             //exited
     Signal(i32),           //exited
     Running(ProgramState), // running
+    Backoff(Duration),     //waiting before a restart attempt
     Nothing,               //not launched
 }
 
@@ -36,6 +38,11 @@ impl From<StateError> for StatusError {
 
 impl TMProgram {
     pub fn status(&mut self) -> Result<ProgramStatus, StatusError> {
+        if let Some(until) = self.backoff_until {
+            return Ok(ProgramStatus::Backoff(
+                until.saturating_duration_since(Instant::now()),
+            ));
+        }
         match self.child.as_mut() {
             None => Ok(ProgramStatus::Nothing),
             Some(child) => match child.try_wait() {
@@ -54,4 +61,22 @@ impl TMProgram {
             },
         }
     }
+
+    /// One word summary of the state of the program
+    pub fn state_label(&mut self) -> &'static str {
+        if self.fatal {
+            return "FATAL";
+        }
+        if self.completed {
+            return "COMPLETED";
+        }
+        match self.status() {
+            Ok(ProgramStatus::Running(_)) => "RUNNING",
+            Ok(ProgramStatus::Code(_) | ProgramStatus::Signal(_)) => "EXITED",
+            Ok(ProgramStatus::Backoff(_)) => "BACKOFF",
+            Ok(ProgramStatus::Nothing) if self.waiting_dependencies => "WAITING",
+            Ok(ProgramStatus::Nothing) => "STOPPED",
+            Err(_) => "UNKNOWN",
+        }
+    }
 }
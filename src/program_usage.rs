@@ -0,0 +1,87 @@
+use std::time::Duration;
+
+use crate::program::TMProgram;
+use crate::program_state::StateError;
+
+#[derive(Debug)]
+pub struct ProgramUsage {
+    /// Resident memory in bytes
+    pub rss: u64,
+    /// CPU time spent in user and kernel mode since launch
+    pub cpu_time: Duration,
+}
+
+impl TMProgram {
+    #[cfg(target_os = "linux")]
+    pub fn usage(&self) -> Result<ProgramUsage, StateError> {
+        let pid = match &self.child {
+            None => return Err(StateError::ProgramNotLaunched),
+            Some(x) => x.id(),
+        };
+        let stat = match std::fs::read_to_string(format!("/proc/{pid}/stat")) {
+            Err(e) => return Err(StateError::RuntimeError(Box::new(e))),
+            Ok(x) => x,
+        };
+        let statm = match std::fs::read_to_string(format!("/proc/{pid}/statm")) {
+            Err(e) => return Err(StateError::RuntimeError(Box::new(e))),
+            Ok(x) => x,
+        };
+        // the command name may contain spaces, the fields start after its closing parenthesis
+        let fields: Vec<&str> = match stat.rfind(')') {
+            None => return Err(StateError::UnknownState("Malformed stat".to_string())),
+            Some(x) => stat[x + 1..].split_whitespace().collect(),
+        };
+        let field = |idx: usize| -> Result<u64, StateError> {
+            fields
+                .get(idx)
+                .and_then(|x| x.parse().ok())
+                .ok_or(StateError::UnknownState("Malformed stat".to_string()))
+        };
+        // utime and stime are the 14th and 15th fields of the whole line
+        let ticks = field(11)? + field(12)?;
+        let pages: u64 = match statm.split_whitespace().nth(1).map(|x| x.parse()) {
+            Some(Ok(x)) => x,
+            _ => return Err(StateError::UnknownState("Malformed statm".to_string())),
+        };
+        let (ticks_per_sec, page_size) = unsafe {
+            (
+                libc::sysconf(libc::_SC_CLK_TCK),
+                libc::sysconf(libc::_SC_PAGESIZE),
+            )
+        };
+        Ok(ProgramUsage {
+            rss: pages * page_size as u64,
+            cpu_time: Duration::from_secs_f64(ticks as f64 / ticks_per_sec as f64),
+        })
+    }
+
+    #[cfg(target_os = "macos")]
+    pub fn usage(&self) -> Result<ProgramUsage, StateError> {
+        use libc::{c_void, proc_pidinfo, proc_taskinfo, PROC_PIDTASKINFO};
+        use std::mem;
+
+        let pid = match &self.child {
+            None => return Err(StateError::ProgramNotLaunched),
+            Some(x) => x.id(),
+        };
+        let mut task_info: proc_taskinfo = unsafe { mem::zeroed() };
+        let size = unsafe {
+            proc_pidinfo(
+                pid as i32,
+                PROC_PIDTASKINFO,
+                0,
+                &mut task_info as *mut _ as *mut c_void,
+                size_of::<proc_taskinfo>() as i32,
+            )
+        };
+        if size <= 0 {
+            return Err(StateError::RuntimeError(Box::new(
+                std::io::Error::last_os_error(),
+            )));
+        }
+        Ok(ProgramUsage {
+            rss: task_info.pti_resident_size,
+            cpu_time: Duration::from_nanos(task_info.pti_total_user + task_info.pti_total_system),
+        })
+    }
+}
@@ -0,0 +1,61 @@
+use std::fmt::{self, Display, Formatter};
+use std::io;
+
+use crate::config::TMRlimits;
+
+impl TMRlimits {
+    pub fn is_empty(&self) -> bool {
+        self.list().iter().all(|(_, _, value)| value.is_none())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn list(&self) -> [(&'static str, libc::__rlimit_resource_t, Option<u64>); 5] {
+        [
+            ("nofile", libc::RLIMIT_NOFILE, self.nofile),
+            ("as", libc::RLIMIT_AS, self.address_space),
+            ("core", libc::RLIMIT_CORE, self.core),
+            ("nproc", libc::RLIMIT_NPROC, self.nproc),
+            ("cpu", libc::RLIMIT_CPU, self.cpu),
+        ]
+    }
+
+    #[cfg(target_os = "macos")]
+    fn list(&self) -> [(&'static str, libc::c_int, Option<u64>); 5] {
+        [
+            ("nofile", libc::RLIMIT_NOFILE, self.nofile),
+            ("as", libc::RLIMIT_AS, self.address_space),
+            ("core", libc::RLIMIT_CORE, self.core),
+            ("nproc", libc::RLIMIT_NPROC, self.nproc),
+            ("cpu", libc::RLIMIT_CPU, self.cpu),
+        ]
+    }
+
+    /// Set the limits on the current process, meant to be called in the child between fork and
+    /// exec so it only use async-signal-safe functions
+    pub fn apply(&self) -> io::Result<()> {
+        for (_, resource, value) in self.list() {
+            let Some(value) = value else {
+                continue;
+            };
+            let limit = libc::rlimit {
+                rlim_cur: value as libc::rlim_t,
+                rlim_max: value as libc::rlim_t,
+            };
+            if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for TMRlimits {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let limits: Vec<String> = self
+            .list()
+            .iter()
+            .filter_map(|(name, _, value)| value.map(|x| format!("{name}={x}")))
+            .collect();
+        write!(f, "{}", limits.join(" "))
+    }
+}
@@ -0,0 +1,297 @@
+use std::fmt::{self, Display, Formatter};
+use std::process::ExitStatus;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Deserialize;
+
+/// Cron expression: "minute hour day-of-month month day-of-week", each field being "*", a number,
+/// a range "a-b", a list "a,b" or any of them with a step "*/5", or one of @yearly, @monthly,
+/// @weekly, @daily and @hourly
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct Schedule {
+    source: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// The day of month field is "*"
+    any_day: bool,
+    /// The day of week field is "*"
+    any_weekday: bool,
+}
+
+/// Value bits of a field, and whether it was "*"
+fn parse_field(field: &str, min: u32, max: u32) -> Result<(u64, bool), String> {
+    let mut mask = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(x) if x > 0 => (range, Some(x)),
+                _ => return Err(format!("invalid step in {part}")),
+            },
+            None => (part, None),
+        };
+        let number = |x: &str| {
+            x.parse::<u32>()
+                .map_err(|_| format!("invalid value {x} in {field}"))
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (number(start)?, number(end)?),
+            // "5/10" runs from 5 to the end
+            None if step.is_some() => (number(range)?, max),
+            None => (number(range)?, number(range)?),
+        };
+        if start < min || end > max || start > end {
+            return Err(format!("{part} out of range {min}-{max}"));
+        }
+        for value in (start..=end).step_by(step.unwrap_or(1) as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok((mask, field.starts_with('*')))
+}
+
+impl TryFrom<String> for Schedule {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        let expression = match source.as_str() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            x => x,
+        };
+        let [minutes, hours, days, months, weekdays] = expression
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .try_into()
+            .map_err(|_| format!("{source}: a schedule needs 5 fields"))?;
+        let error = |e: String| format!("{source}: {e}");
+        let (minutes, _) = parse_field(minutes, 0, 59).map_err(error)?;
+        let (hours, _) = parse_field(hours, 0, 23).map_err(error)?;
+        let (days, any_day) = parse_field(days, 1, 31).map_err(error)?;
+        let (months, _) = parse_field(months, 1, 12).map_err(error)?;
+        let (mut weekdays, any_weekday) = parse_field(weekdays, 0, 7).map_err(error)?;
+        // 7 is sunday too
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Self {
+            source,
+            minutes,
+            hours,
+            days,
+            months,
+            weekdays,
+            any_day,
+            any_weekday,
+        })
+    }
+}
+
+impl Display for Schedule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// Fix the fields of tm that went out of range and return the matching time
+fn normalize(tm: &mut libc::tm) -> i64 {
+    tm.tm_isdst = -1;
+    unsafe { libc::mktime(tm) as i64 }
+}
+
+impl Schedule {
+    fn matches_day(&self, tm: &libc::tm) -> bool {
+        let day = self.days & (1 << tm.tm_mday) != 0;
+        let weekday = self.weekdays & (1 << tm.tm_wday) != 0;
+        // like cron, a day matches either field when both are restricted
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    /// First time matching the schedule strictly after the given time, in local time, None if
+    /// it never matches (e.g. "0 0 31 2 *")
+    pub fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        let secs = time.duration_since(UNIX_EPOCH).ok()?.as_secs() as libc::time_t;
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        if unsafe { libc::localtime_r(&secs, &mut tm) }.is_null() {
+            return None;
+        }
+        tm.tm_sec = 0;
+        tm.tm_min += 1;
+        let mut next = normalize(&mut tm);
+        // a few years of days is enough to find any valid date
+        for _ in 0..5000 {
+            if self.months & (1 << (tm.tm_mon + 1)) == 0 {
+                tm.tm_mon += 1;
+                tm.tm_mday = 1;
+                tm.tm_hour = 0;
+                tm.tm_min = 0;
+            } else if !self.matches_day(&tm) {
+                tm.tm_mday += 1;
+                tm.tm_hour = 0;
+                tm.tm_min = 0;
+            } else if self.hours & (1 << tm.tm_hour) == 0 {
+                tm.tm_hour += 1;
+                tm.tm_min = 0;
+            } else if self.minutes & (1 << tm.tm_min) == 0 {
+                tm.tm_min += 1;
+            } else {
+                return Some(UNIX_EPOCH + Duration::from_secs(next.try_into().ok()?));
+            }
+            next = normalize(&mut tm);
+        }
+        None
+    }
+}
+
+/// "2026-10-19 14:05:00" in local time
+pub fn format_time(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs() as libc::time_t);
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&secs, &mut tm) };
+    format!(
+        "{}-{:02}-{:02} {:02}:{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+/// Runs of a scheduled program, updated by the supervisor
+#[derive(Debug, Default)]
+pub struct ScheduleState {
+    pub next_run: Option<SystemTime>,
+    /// When the last run started
+    pub last_run: Option<SystemTime>,
+    /// How the last run ended, None while it is running
+    pub last_status: Option<ExitStatus>,
+    pub last_duration: Option<Duration>,
+    /// A run is due as soon as the current one exits
+    pub queued: bool,
+}
+
+impl ScheduleState {
+    pub fn start(&mut self) {
+        self.last_run = Some(SystemTime::now());
+        self.last_status = None;
+        self.last_duration = None;
+    }
+
+    pub fn finish(&mut self, status: ExitStatus) {
+        self.last_status = Some(status);
+        self.last_duration = self.last_run.and_then(|x| x.elapsed().ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(source: &str) -> Schedule {
+        Schedule::try_from(source.to_string()).unwrap()
+    }
+
+    /// A local time, the one next_after works in
+    fn local(year: i32, month: i32, day: i32, hour: i32, min: i32) -> SystemTime {
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        tm.tm_year = year - 1900;
+        tm.tm_mon = month - 1;
+        tm.tm_mday = day;
+        tm.tm_hour = hour;
+        tm.tm_min = min;
+        UNIX_EPOCH + Duration::from_secs(normalize(&mut tm) as u64)
+    }
+
+    #[test]
+    fn parse_fields() {
+        let x = schedule("*/15 0-6/2 1,15 * 1-5");
+        assert_eq!(x.minutes, 1 << 0 | 1 << 15 | 1 << 30 | 1 << 45);
+        assert_eq!(x.hours, 1 << 0 | 1 << 2 | 1 << 4 | 1 << 6);
+        assert_eq!(x.days, 1 << 1 | 1 << 15);
+        assert_eq!(x.months, 0b1_1111_1111_1110);
+        assert_eq!(x.weekdays, 0b11_1110);
+        assert!(!x.any_day && !x.any_weekday);
+        assert_eq!(x.to_string(), "*/15 0-6/2 1,15 * 1-5");
+    }
+
+    #[test]
+    fn parse_sunday_as_7() {
+        assert_eq!(schedule("0 0 * * 7").weekdays & 1, 1);
+    }
+
+    #[test]
+    fn parse_aliases() {
+        assert_eq!(schedule("@daily").minutes, schedule("0 0 * * *").minutes);
+        assert_eq!(schedule("@weekly").weekdays, 1);
+        assert_eq!(schedule("@monthly").days, 1 << 1);
+    }
+
+    #[test]
+    fn parse_errors() {
+        for source in [
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+        ] {
+            assert!(Schedule::try_from(source.to_string()).is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn next_minute_step() {
+        let x = schedule("*/15 * * * *");
+        let next = x.next_after(local(2025, 1, 10, 10, 7));
+        assert_eq!(next, Some(local(2025, 1, 10, 10, 15)));
+    }
+
+    #[test]
+    fn next_is_strictly_after() {
+        let x = schedule("*/15 * * * *");
+        let next = x.next_after(local(2025, 1, 10, 10, 15));
+        assert_eq!(next, Some(local(2025, 1, 10, 10, 30)));
+    }
+
+    #[test]
+    fn next_day_and_month() {
+        let next = schedule("@daily").next_after(local(2025, 1, 31, 23, 59));
+        assert_eq!(next, Some(local(2025, 2, 1, 0, 0)));
+        let next = schedule("@monthly").next_after(local(2025, 7, 15, 12, 0));
+        assert_eq!(next, Some(local(2025, 8, 1, 0, 0)));
+    }
+
+    #[test]
+    fn next_day_of_month_or_week() {
+        // 2025-01-01 is a wednesday, the 3rd a friday
+        let next = schedule("0 12 13 * 5").next_after(local(2025, 1, 1, 0, 0));
+        assert_eq!(next, Some(local(2025, 1, 3, 12, 0)));
+        let next = schedule("0 12 13 * *").next_after(local(2025, 1, 1, 0, 0));
+        assert_eq!(next, Some(local(2025, 1, 13, 12, 0)));
+    }
+
+    #[test]
+    fn next_never() {
+        assert_eq!(
+            schedule("0 0 31 2 *").next_after(local(2025, 1, 1, 0, 0)),
+            None
+        );
+    }
+}
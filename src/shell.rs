@@ -1,12 +1,19 @@
 use std::fmt::Display;
+use std::io::Read;
 
-use tokio::io::{self, stdin, AsyncBufReadExt};
-use tokio::io::{AsyncWriteExt, Stdout};
+use tokio::io::{self, AsyncWriteExt, Stdout};
+use tokio::sync::mpsc;
 
 pub struct Shell {
+    #[allow(dead_code)]
     shell: String,
     stdout: Stdout,
     og_termios: libc::termios,
+    /// What a thread reads from stdin, the only reader of stdin so no input is lost when attach
+    /// hands the terminal back to the shell
+    input: mpsc::UnboundedReceiver<Vec<u8>>,
+    /// Input read but not used yet
+    pending: Vec<u8>,
 }
 
 #[derive(Debug)]
@@ -16,7 +23,9 @@ pub enum TryNewError {
 
 impl Display for TryNewError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.to_string())
+        match self {
+            Self::TcGetAttr => write!(f, "failed to get terminal attributes"),
+        }
     }
 }
 
@@ -30,10 +39,21 @@ impl Shell {
             Ok(x) => x,
             Err(_) => return Err(TryNewError::TcGetAttr),
         };
+        let (input_tx, input) = mpsc::unbounded_channel();
+        std::thread::spawn(move || {
+            let mut buf = [0; 1024];
+            while let Ok(len @ 1..) = std::io::stdin().read(&mut buf) {
+                if input_tx.send(buf[..len].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
         let shell = Shell {
             og_termios,
             stdout,
             shell: shell.to_string(),
+            input,
+            pending: Vec::new(),
         };
         Ok(shell)
     }
@@ -64,10 +84,32 @@ impl Shell {
     pub async fn read_line(&mut self) -> Result<String, io::Error> {
         self.stdout.write_all(b"$>").await?;
         self.stdout.flush().await?;
-        let mut user_input = String::new();
-        let mut reader = io::BufReader::new(stdin());
-        reader.read_line(&mut user_input).await?;
-        Ok(user_input)
+        loop {
+            if let Some(idx) = self.pending.iter().position(|x| *x == b'\n') {
+                let line: Vec<u8> = self.pending.drain(..=idx).collect();
+                return Ok(String::from_utf8_lossy(&line).into_owned());
+            }
+            match self.input.recv().await {
+                Some(x) => self.pending.extend(x),
+                None => {
+                    let line = std::mem::take(&mut self.pending);
+                    return Ok(String::from_utf8_lossy(&line).into_owned());
+                }
+            }
+        }
+    }
+
+    /// Next input from the terminal as it comes, None once stdin is closed, cancel safe
+    pub async fn read_input(&mut self) -> Option<Vec<u8>> {
+        if !self.pending.is_empty() {
+            return Some(std::mem::take(&mut self.pending));
+        }
+        self.input.recv().await
+    }
+
+    /// Give back input read with read_input but not used
+    pub fn unread(&mut self, data: &[u8]) {
+        self.pending.splice(..0, data.iter().copied());
     }
 }
 
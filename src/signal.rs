@@ -0,0 +1,65 @@
+use libc::c_int;
+
+const SIGNALS: [(&str, c_int); 15] = [
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ABRT", libc::SIGABRT),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("WINCH", libc::SIGWINCH),
+];
+
+/// Parse a signal given by name ("SIGTERM" or "TERM", case insensitive) or by number ("15")
+pub fn parse_signal(value: &str) -> Option<c_int> {
+    if let Ok(x) = value.parse::<c_int>() {
+        return (x > 0 && x < 32).then_some(x);
+    }
+    let value = value.to_uppercase();
+    let name = value.strip_prefix("SIG").unwrap_or(&value);
+    SIGNALS.iter().find(|x| x.0 == name).map(|x| x.1)
+}
+
+/// Name of a signal ("SIGTERM"), or its number when it is not a known one
+pub fn signal_name(signal: c_int) -> String {
+    match SIGNALS.iter().find(|x| x.1 == signal) {
+        Some(x) => format!("SIG{}", x.0),
+        None => signal.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_names() {
+        assert_eq!(parse_signal("SIGTERM"), Some(libc::SIGTERM));
+        assert_eq!(parse_signal("term"), Some(libc::SIGTERM));
+        assert_eq!(parse_signal("SigUsr1"), Some(libc::SIGUSR1));
+        assert_eq!(parse_signal("SIGNOPE"), None);
+        assert_eq!(parse_signal(""), None);
+    }
+
+    #[test]
+    fn parse_numbers() {
+        assert_eq!(parse_signal("9"), Some(libc::SIGKILL));
+        assert_eq!(parse_signal("0"), None);
+        assert_eq!(parse_signal("32"), None);
+        assert_eq!(parse_signal("-15"), None);
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(signal_name(libc::SIGHUP), "SIGHUP");
+        assert_eq!(signal_name(31), "31");
+    }
+}
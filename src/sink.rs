@@ -0,0 +1,197 @@
+use std::io;
+use std::os::unix::net::UnixDatagram;
+
+use crate::config::{SyslogFormat, TMSink};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error = 3,
+    Info = 6,
+}
+
+/// syslog facility of every message, LOG_DAEMON
+const FACILITY: u8 = 3;
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+        return "-".to_string();
+    }
+    let len = buf.iter().position(|x| *x == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+/// Current time broken down in local time or UTC, with the microsecs
+fn now(utc: bool) -> (libc::tm, i64) {
+    let mut time: libc::timeval = unsafe { std::mem::zeroed() };
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe {
+        libc::gettimeofday(&mut time, std::ptr::null_mut());
+        match utc {
+            true => libc::gmtime_r(&time.tv_sec, &mut tm),
+            false => libc::localtime_r(&time.tv_sec, &mut tm),
+        };
+    }
+    (tm, time.tv_usec as i64)
+}
+
+/// "Oct  9 02:57:01"
+pub fn rfc3164_timestamp() -> String {
+    let (tm, _) = now(false);
+    format!(
+        "{} {:2} {:02}:{:02}:{:02}",
+        MONTHS[tm.tm_mon as usize % 12],
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+/// "2026-10-09T02:57:01.123456Z"
+pub fn rfc5424_timestamp() -> String {
+    let (tm, usec) = now(true);
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}.{usec:06}Z",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+/// Append a journald field, using the binary form when the value spans several lines
+fn journald_field(datagram: &mut Vec<u8>, key: &str, value: &str) {
+    datagram.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        datagram.push(b'\n');
+        datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        datagram.push(b'=');
+    }
+    datagram.extend_from_slice(value.as_bytes());
+    datagram.push(b'\n');
+}
+
+impl TMSink {
+    /// Send a message to the sink, identifier is the program name or "taskmaster"
+    pub fn send(
+        &self,
+        identifier: &str,
+        pid: u32,
+        severity: Severity,
+        msg: &str,
+    ) -> io::Result<()> {
+        let (socket, datagram) = match self {
+            Self::Syslog { format, socket } => {
+                let pri = FACILITY * 8 + severity as u8;
+                let datagram = match format {
+                    SyslogFormat::Rfc3164 => format!(
+                        "<{pri}>{} {} {identifier}[{pid}]: {msg}",
+                        rfc3164_timestamp(),
+                        hostname()
+                    ),
+                    SyslogFormat::Rfc5424 => format!(
+                        "<{pri}>1 {} {} {identifier} {pid} - - {msg}",
+                        rfc5424_timestamp(),
+                        hostname()
+                    ),
+                };
+                (socket, datagram.into_bytes())
+            }
+            Self::Journald { socket } => {
+                let mut datagram = Vec::new();
+                journald_field(&mut datagram, "MESSAGE", msg);
+                journald_field(&mut datagram, "PRIORITY", &(severity as u8).to_string());
+                journald_field(&mut datagram, "SYSLOG_IDENTIFIER", identifier);
+                journald_field(&mut datagram, "SYSLOG_PID", &pid.to_string());
+                (socket, datagram)
+            }
+        };
+        UnixDatagram::unbound()?.send_to(&datagram, socket)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Send a message through the sink to a socket standing in for /dev/log or journald
+    fn send(sink: impl FnOnce(String) -> TMSink, severity: Severity, msg: &str) -> Vec<u8> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("socket");
+        let server = UnixDatagram::bind(&path).unwrap();
+        sink(path.to_string_lossy().into_owned())
+            .send("web", 42, severity, msg)
+            .unwrap();
+        let mut buf = [0; 4096];
+        let len = server.recv(&mut buf).unwrap();
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn rfc3164() {
+        let sink = |socket| TMSink::Syslog {
+            format: SyslogFormat::Rfc3164,
+            socket,
+        };
+        let datagram = String::from_utf8(send(sink, Severity::Error, "boom")).unwrap();
+        // LOG_DAEMON and LOG_ERR
+        let rest = datagram.strip_prefix("<27>").unwrap();
+        // "Oct  9 02:57:01 host web[42]: boom"
+        assert!(MONTHS.contains(&&rest[..3]));
+        assert_eq!(&rest[6..7], " ");
+        assert_eq!(&rest[9..10], ":");
+        assert_eq!(&rest[12..13], ":");
+        let (host, rest) = rest[16..].split_once(' ').unwrap();
+        assert_eq!(host, hostname());
+        assert_eq!(rest, "web[42]: boom");
+    }
+
+    #[test]
+    fn rfc5424() {
+        let sink = |socket| TMSink::Syslog {
+            format: SyslogFormat::Rfc5424,
+            socket,
+        };
+        let datagram = String::from_utf8(send(sink, Severity::Info, "up")).unwrap();
+        let fields: Vec<&str> = datagram.splitn(8, ' ').collect();
+        // LOG_DAEMON and LOG_INFO, version 1
+        assert_eq!(fields[0], "<30>1");
+        // "2026-10-09T02:57:01.123456Z"
+        let timestamp = fields[1].as_bytes();
+        assert_eq!(timestamp.len(), 27);
+        assert_eq!(timestamp[10], b'T');
+        assert_eq!(timestamp[19], b'.');
+        assert_eq!(timestamp[26], b'Z');
+        assert_eq!(fields[2], hostname());
+        assert_eq!(fields[3..], ["web", "42", "-", "-", "up"]);
+    }
+
+    #[test]
+    fn journald() {
+        let sink = |socket| TMSink::Journald { socket };
+        let datagram = send(sink, Severity::Info, "started");
+        assert_eq!(
+            datagram,
+            b"MESSAGE=started\nPRIORITY=6\nSYSLOG_IDENTIFIER=web\nSYSLOG_PID=42\n"
+        );
+    }
+
+    #[test]
+    fn journald_multiline() {
+        let sink = |socket| TMSink::Journald { socket };
+        let datagram = send(sink, Severity::Error, "a\nb");
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&3u64.to_le_bytes());
+        expected.extend_from_slice(b"a\nb\nPRIORITY=3\n");
+        assert!(datagram.starts_with(&expected));
+    }
+}
@@ -0,0 +1,187 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::child::{process_age, process_start_time, TMChild};
+use crate::config::{TMConfig, TMProgramConfig};
+use crate::logger;
+use crate::program::TMProgram;
+use crate::signal::parse_signal;
+
+/// What is saved about an instance so the next taskmaster can take over
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SavedProgram {
+    pub name: String,
+    pub instance: u32,
+    /// Process of the instance when it was running
+    pub pid: Option<u32>,
+    pub start_time: Option<u64>,
+    pub restarts: u32,
+    pub launches: u64,
+    #[serde(default)]
+    pub restarts_total: u64,
+    /// The instance was stopped with the kill command
+    #[serde(default)]
+    pub stopped_by_user: bool,
+}
+
+/// Where the state is saved, from the config
+static PATH: Mutex<Option<String>> = Mutex::new(None);
+
+/// State of the last write without the start times, to only write the state file when an
+/// instance changes
+static LAST_SAVED: Mutex<Vec<SavedProgram>> = Mutex::new(Vec::new());
+
+pub fn set_path(path: Option<String>) {
+    *PATH.lock().unwrap() = path;
+}
+
+/// Read the state saved by the previous taskmaster, nothing when there is none
+pub fn load(path: &str) -> Vec<SavedProgram> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(x) => x,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Vec::new(),
+        Err(e) => {
+            logger::error(&format!("failed to read state file {path}: {e}"));
+            return Vec::new();
+        }
+    };
+    match serde_json::from_str(&content) {
+        Ok(x) => x,
+        Err(e) => {
+            logger::error(&format!("failed to parse state file {path}: {e}"));
+            Vec::new()
+        }
+    }
+}
+
+/// Write the state of every instance when it changed since the last write, through a temporary
+/// file so a crash never leaves a partial state file
+pub fn save(programs: &mut [TMProgram]) {
+    let Some(path) = PATH.lock().unwrap().clone() else {
+        return;
+    };
+    let saved: Vec<SavedProgram> = programs.iter_mut().map(|x| x.saved()).collect();
+    let last_saved = &mut LAST_SAVED.lock().unwrap();
+    if **last_saved == saved {
+        return;
+    }
+    // only read from /proc once something changed
+    let mut content = saved.clone();
+    for x in content.iter_mut() {
+        x.start_time = x.pid.and_then(process_start_time);
+    }
+    let Ok(content) = serde_json::to_string_pretty(&content) else {
+        return;
+    };
+    let tmp = format!("{path}.tmp");
+    if let Err(e) = std::fs::write(&tmp, &content).and_then(|_| std::fs::rename(&tmp, &path)) {
+        logger::error(&format!("failed to write state file {path}: {e}"));
+        return;
+    }
+    **last_saved = saved;
+}
+
+/// Whether the process of an instance can be taken over: the output of the other processes
+/// goes through pipes of the previous taskmaster, their next write would kill them, and the
+/// sockets they listen on would keep taskmaster from opening them again
+fn adoptable(config: &TMProgramConfig) -> bool {
+    config.direct_output && config.listen.is_empty()
+}
+
+/// Stop the processes left running by the previous taskmaster that cannot be adopted and wait
+/// for them to exit, so they release their sockets before the instances are created
+pub fn stop_unadoptable(saved: &[SavedProgram], config: &TMConfig) {
+    let mut left: Vec<(u32, u64)> = Vec::new();
+    let mut graceful_period = 0;
+    for x in saved.iter() {
+        let Some(program) = config.programs.get(&x.name) else {
+            continue;
+        };
+        let (Some(pid), Some(start_time)) = (x.pid, x.start_time) else {
+            continue;
+        };
+        if adoptable(program) || process_start_time(pid) != Some(start_time) {
+            continue;
+        }
+        logger::info(&format!(
+            "[{}:{}] stopping process {pid} left running, it cannot be adopted",
+            x.name, x.instance
+        ));
+        if let Some(signal) = parse_signal(&program.stopsignal) {
+            unsafe { libc::kill(-(pid as libc::pid_t), signal) };
+        }
+        graceful_period = graceful_period.max(program.graceful_period);
+        left.push((pid, start_time));
+    }
+    let mut deadline = Instant::now() + Duration::from_secs(graceful_period.into());
+    let mut killed = false;
+    loop {
+        left.retain(|(pid, start_time)| process_start_time(*pid) == Some(*start_time));
+        if left.is_empty() {
+            return;
+        }
+        if Instant::now() >= deadline {
+            if killed {
+                logger::error("processes left running did not exit after SIGKILL");
+                return;
+            }
+            for (pid, _) in left.iter() {
+                unsafe { libc::kill(-(*pid as libc::pid_t), libc::SIGKILL) };
+            }
+            killed = true;
+            deadline = Instant::now() + Duration::from_secs(5);
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+impl TMProgram {
+    fn saved(&mut self) -> SavedProgram {
+        let pid = match self.is_running() {
+            true => self.child.as_ref().map(|x| x.id()),
+            false => None,
+        };
+        SavedProgram {
+            name: self.name.clone(),
+            instance: self.instance,
+            pid,
+            start_time: None,
+            restarts: self.restarts,
+            launches: self.launches,
+            restarts_total: self.restarts_total,
+            stopped_by_user: self.stopped_by_user,
+        }
+    }
+
+    /// Restore the counters of the instance and take over its process when it is still the
+    /// one that was saved, return whether it was adopted
+    pub fn adopt(&mut self, saved: &SavedProgram) -> bool {
+        self.restarts = saved.restarts;
+        self.launches = saved.launches;
+        self.restarts_total = saved.restarts_total;
+        self.stopped_by_user = saved.stopped_by_user;
+        let (Some(pid), Some(start_time)) = (saved.pid, saved.start_time) else {
+            return false;
+        };
+        if process_start_time(pid) != Some(start_time) {
+            return false;
+        }
+        if !adoptable(&self.config) {
+            return false;
+        }
+        self.child = Some(TMChild::Adopted {
+            pid,
+            start_time,
+            status: None,
+        });
+        self.started_at = process_age(start_time).and_then(|x| Instant::now().checked_sub(x));
+        self.exit_handled = false;
+        logger::info(&format!(
+            "[{}] adopted running process {pid}",
+            self.display_name()
+        ));
+        true
+    }
+}
@@ -0,0 +1,614 @@
+use std::collections::VecDeque;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::config::{AutoRestart, Overlap, ProgramType, TMBackoff, TMProgramConfig};
+use crate::event::{self, EventKind};
+use crate::logger;
+use crate::program::TMProgram;
+use crate::schedule::format_time;
+use crate::state;
+
+/// Instances restarted one at a time, each one must be healthy before the next one is restarted
+struct Rollout {
+    /// Name and instance number of the instances left to restart
+    pending: VecDeque<(String, u32)>,
+    /// Instance being restarted, with when it must be healthy
+    current: Option<((String, u32), Instant)>,
+}
+
+static ROLLOUTS: Mutex<Vec<Rollout>> = Mutex::new(Vec::new());
+
+/// Restart the given instances one at a time on the next ticks, return false without doing
+/// anything when one of them is already part of a rolling restart
+pub fn start_rollout(instances: Vec<(String, u32)>) -> bool {
+    let rollouts = &mut ROLLOUTS.lock().unwrap();
+    let busy = rollouts.iter().any(|rollout| {
+        rollout
+            .pending
+            .iter()
+            .chain(rollout.current.iter().map(|x| &x.0))
+            .any(|x| instances.contains(x))
+    });
+    if busy {
+        return false;
+    }
+    rollouts.push(Rollout {
+        pending: instances.into(),
+        current: None,
+    });
+    true
+}
+
+/// Periodically check every program: finish the graceful stops, restart the programs that exited
+/// and enforce the watchdog and probes
+pub async fn supervise(programs: Arc<Mutex<Vec<TMProgram>>>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        let programs = &mut programs.lock().unwrap();
+        programs.retain_mut(|x| !x.removed || x.is_running());
+        launch_ready(programs);
+        for program in programs.iter_mut() {
+            if program.stop_deadline.is_some() {
+                finish_stop(program);
+                continue;
+            }
+            if program.backoff_until.is_some() {
+                finish_backoff(program);
+                continue;
+            }
+            check_exit(program);
+            check_schedule(program);
+            if program.restarts > 0 && program.is_healthy() {
+                program.restarts = 0;
+            }
+            if let Some(reason) = check_watchdog(program).or_else(|| check_probes(program)) {
+                logger::info(&format!(
+                    "[{}] {reason}, restarting",
+                    program.display_name()
+                ));
+                program.restarts_total += 1;
+                program.restart_after_stop = true;
+                if let Err(e) = program.stop() {
+                    logger::error(&format!("[{}] failed to stop: {e}", program.display_name()));
+                    program.restart_after_stop = false;
+                }
+            }
+        }
+        ROLLOUTS
+            .lock()
+            .unwrap()
+            .retain_mut(|x| advance_rollout(x, programs));
+        state::save(programs);
+    }
+}
+
+/// Move the rollout forward, return false once it is over
+fn advance_rollout(rollout: &mut Rollout, programs: &mut [TMProgram]) -> bool {
+    let Some(((name, instance), deadline)) = &rollout.current else {
+        let Some((name, instance)) = rollout.pending.pop_front() else {
+            logger::info("rolling restart done");
+            return false;
+        };
+        let Some(program) = programs
+            .iter_mut()
+            .find(|x| x.name == name && x.instance == instance)
+        else {
+            return true;
+        };
+        logger::info(&format!(
+            "[{}] rolling restart, {} left after this one",
+            program.display_name(),
+            rollout.pending.len()
+        ));
+        let deadline = Instant::now()
+            + Duration::from_secs(
+                (program.config.graceful_period + program.config.rollout_timeout).into(),
+            );
+        rollout.current = Some(((name, instance), deadline));
+        program.stopped_by_user = false;
+        program.backoff_until = None;
+        program.restarts = 0;
+        let result = match program.is_running() {
+            true => {
+                program.restart_after_stop = true;
+                program.stop()
+            }
+            false => program.launch(),
+        };
+        if let Err(e) = result {
+            program.restart_after_stop = false;
+            logger::error(&format!(
+                "[{}] rolling restart aborted, failed to restart: {e}",
+                program.display_name()
+            ));
+            return false;
+        }
+        return true;
+    };
+    let Some(program) = programs
+        .iter_mut()
+        .find(|x| x.name == *name && x.instance == *instance)
+    else {
+        rollout.current = None;
+        return true;
+    };
+    if program.stop_deadline.is_some() || program.restart_after_stop {
+        return true;
+    }
+    if program.is_healthy() {
+        logger::info(&format!("[{}] healthy", program.display_name()));
+        rollout.current = None;
+        return true;
+    }
+    let failure = if program.fatal
+        || program.exit_handled
+        || program.backoff_until.is_some()
+        || program.child.is_none()
+    {
+        "exited"
+    } else if *deadline <= Instant::now() {
+        "not healthy in time"
+    } else {
+        return true;
+    };
+    logger::error(&format!(
+        "[{}] rolling restart aborted, {failure}, {} instances not restarted",
+        program.display_name(),
+        rollout.pending.len()
+    ));
+    false
+}
+
+/// Launch the programs waiting for dependencies that are now all healthy or completed
+fn launch_ready(programs: &mut [TMProgram]) {
+    let mut healthy: Vec<(String, bool)> = Vec::new();
+    for program in programs.iter_mut() {
+        let is_healthy = program.satisfies_dependents();
+        match healthy.iter_mut().find(|x| x.0 == program.name) {
+            Some(x) => x.1 &= is_healthy,
+            None => healthy.push((program.name.clone(), is_healthy)),
+        }
+    }
+    for program in programs.iter_mut() {
+        if !program.waiting_dependencies {
+            continue;
+        }
+        let ready = program
+            .config
+            .depends_on
+            .iter()
+            .all(|dep| healthy.iter().any(|x| x.0 == *dep && x.1));
+        if !ready {
+            continue;
+        }
+        logger::info(&format!(
+            "[{}] dependencies ready, launching",
+            program.display_name()
+        ));
+        if let Err(e) = program.launch() {
+            logger::error(&format!(
+                "[{}] failed to launch: {e}",
+                program.display_name()
+            ));
+            program.waiting_dependencies = false;
+        }
+    }
+}
+
+/// Stop every program in reverse start order, waiting for each one to exit before stopping the
+/// next so a program never outlives what it depends on
+pub fn shutdown(programs: &mut [TMProgram]) {
+    for program in programs.iter_mut().rev() {
+        if !program.is_running() {
+            continue;
+        }
+        program.restart_after_stop = false;
+        if let Err(e) = program.stop() {
+            logger::error(&format!("[{}] failed to stop: {e}", program.display_name()));
+            if let Err(e) = program.kill() {
+                logger::error(&format!("[{}] failed to kill: {e}", program.display_name()));
+            }
+            program.stop_deadline = Some(Instant::now());
+        }
+        while program.child.is_some() {
+            finish_stop(program);
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+    state::save(programs);
+}
+
+fn finish_stop(program: &mut TMProgram) {
+    let Some(child) = &mut program.child else {
+        program.stop_deadline = None;
+        return;
+    };
+    match child.try_wait() {
+        Ok(None) => {
+            if program.stop_deadline.is_some_and(|x| x <= Instant::now()) {
+                logger::info(&format!(
+                    "[{}] still running after graceful period, killing it",
+                    program.display_name()
+                ));
+                if let Err(e) = program.kill() {
+                    logger::error(&format!("[{}] failed to kill: {e}", program.display_name()));
+                }
+            }
+            return;
+        }
+        Ok(Some(status)) => {
+            program.record_exit(status);
+            program.scheduled.finish(status);
+            event::emit(program.event(EventKind::ProcessStopped).with_exit(status));
+        }
+        Err(e) => logger::error(&format!("[{}] failed to wait: {e}", program.display_name())),
+    }
+    program.child = None;
+    program.stop_deadline = None;
+    if program.restart_after_stop {
+        program.restart_after_stop = false;
+        if let Err(e) = program.launch() {
+            logger::error(&format!(
+                "[{}] failed to launch: {e}",
+                program.display_name()
+            ));
+        }
+    }
+}
+
+/// Handle the exit of the program, putting it in backoff when it must be restarted
+fn check_exit(program: &mut TMProgram) {
+    if program.exit_handled {
+        return;
+    }
+    let status = match &mut program.child {
+        None => return,
+        Some(x) => match x.try_wait() {
+            Ok(Some(x)) => x,
+            _ => return,
+        },
+    };
+    program.exit_handled = true;
+    program.record_exit(status);
+    program.scheduled.finish(status);
+    let expected = status
+        .code()
+        .is_some_and(|x| program.config.exit_status.contains(&x));
+    logger::info(&format!(
+        "[{}] {}, {}",
+        program.display_name(),
+        describe_exit(status),
+        if expected { "expected" } else { "unexpected" }
+    ));
+    event::emit(
+        program
+            .event(EventKind::ProcessExited)
+            .with_exit(status)
+            .with("expected", expected),
+    );
+    if expected && program.config.program_type == ProgramType::Oneshot {
+        logger::info(&format!("[{}] completed", program.display_name()));
+        program.completed = true;
+        return;
+    }
+    if program.config.schedule.is_some() {
+        // a failed run is retried at the next scheduled time
+        return;
+    }
+    let restart = match program.config.autorestart {
+        AutoRestart::Always => true,
+        AutoRestart::Never => false,
+        AutoRestart::UnExpected => !expected,
+    };
+    // a program stopped by the user that exited before the supervisor reaped it
+    if !restart || program.stopped_by_user {
+        return;
+    }
+    if program.restarts >= program.config.number_restart {
+        logger::info(&format!(
+            "[{}] gave up after {} restarts",
+            program.display_name(),
+            program.restarts
+        ));
+        program.fatal = true;
+        event::emit(
+            program
+                .event(EventKind::ProcessFatal)
+                .with("restarts", program.restarts),
+        );
+        return;
+    }
+    program.restarts += 1;
+    program.restarts_total += 1;
+    let delay = backoff_delay(&program.config.backoff, program.restarts);
+    logger::info(&format!(
+        "[{}] restarting in {:.1}s (attempt {}/{})",
+        program.display_name(),
+        delay.as_secs_f64(),
+        program.restarts,
+        program.config.number_restart
+    ));
+    program.backoff_until = Some(Instant::now() + delay);
+    event::emit(
+        program
+            .event(EventKind::ProcessBackoff)
+            .with("attempt", program.restarts)
+            .with("delay_ms", delay.as_millis() as u64),
+    );
+}
+
+pub fn describe_exit(status: ExitStatus) -> String {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => format!("exited with code {code}"),
+        (None, Some(signal)) => format!("killed by signal {signal}"),
+        (None, None) => "exited".to_string(),
+    }
+}
+
+/// Delay before the given restart attempt, starting at 1
+fn backoff_delay(backoff: &TMBackoff, attempt: u32) -> Duration {
+    let factor = (backoff.multiplier as u64).saturating_pow(attempt - 1);
+    let delay = backoff
+        .initial_delay
+        .saturating_mul(factor)
+        .min(backoff.max_delay);
+    let jitter = delay * backoff.jitter.min(100) as u64 / 100;
+    if jitter == 0 {
+        return Duration::from_millis(delay);
+    }
+    // no need for a real rng to spread restarts a bit
+    let random = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.subsec_nanos() as u64);
+    Duration::from_millis(delay - jitter + random % (2 * jitter + 1))
+}
+
+/// Launch the program when its scheduled run is due
+fn check_schedule(program: &mut TMProgram) {
+    let Some(schedule) = &program.config.schedule else {
+        return;
+    };
+    if program.stopped_by_user {
+        program.scheduled.next_run = None;
+        program.scheduled.queued = false;
+        return;
+    }
+    let now = SystemTime::now();
+    let due = match program.scheduled.next_run {
+        None => {
+            program.scheduled.next_run = schedule.next_after(now);
+            false
+        }
+        Some(x) => x <= now,
+    };
+    if due {
+        program.scheduled.next_run = schedule.next_after(now);
+    }
+    let running = program.is_running();
+    if due && running {
+        match program.config.overlap {
+            Overlap::Skip => logger::info(&format!(
+                "[{}] still running, skipping scheduled run",
+                program.display_name()
+            )),
+            Overlap::Queue => program.scheduled.queued = true,
+            Overlap::Replace => {
+                logger::info(&format!(
+                    "[{}] still running, replacing it with the scheduled run",
+                    program.display_name()
+                ));
+                program.restart_after_stop = true;
+                if let Err(e) = program.stop() {
+                    logger::error(&format!("[{}] failed to stop: {e}", program.display_name()));
+                    program.restart_after_stop = false;
+                }
+            }
+        }
+        return;
+    }
+    if running || !(due || program.scheduled.queued) {
+        return;
+    }
+    program.scheduled.queued = false;
+    logger::info(&format!(
+        "[{}] scheduled run, next one at {}",
+        program.display_name(),
+        program
+            .scheduled
+            .next_run
+            .map_or("never".to_string(), format_time)
+    ));
+    if let Err(e) = program.launch() {
+        logger::error(&format!(
+            "[{}] failed to launch: {e}",
+            program.display_name()
+        ));
+    }
+}
+
+/// Give the program its new config, restarting it when the config changed unless the user
+/// stopped it
+pub fn apply_config(program: &mut TMProgram, config: &TMProgramConfig) {
+    if program.config == *config {
+        return;
+    }
+    program.config = config.clone();
+    if program.stopped_by_user {
+        return;
+    }
+    if program.config.schedule.is_some() {
+        // the next run uses the new config, at the time of the new schedule
+        program.scheduled.next_run = None;
+        return;
+    }
+    if program.stop_deadline.is_some() {
+        program.restart_after_stop = true;
+    } else if program.is_running() {
+        logger::info(&format!(
+            "[{}] config changed, restarting",
+            program.display_name()
+        ));
+        program.restart_after_stop = true;
+        if let Err(e) = program.stop() {
+            logger::error(&format!("[{}] failed to stop: {e}", program.display_name()));
+            program.restart_after_stop = false;
+        }
+    } else if let Err(e) = program.autostart() {
+        logger::error(&format!(
+            "[{}] failed to launch: {e}",
+            program.display_name()
+        ));
+    }
+}
+
+/// Keep a removed program that is back in the config, launching it again once it is stopped
+pub fn restore(program: &mut TMProgram) {
+    logger::info(&format!("[{}] back in config", program.display_name()));
+    program.removed = false;
+    if program.stop_deadline.is_some() {
+        program.restart_after_stop = true;
+    }
+}
+
+/// Stop a program that is no longer in the config, the supervisor drops it once it exited
+pub fn remove(program: &mut TMProgram) {
+    logger::info(&format!(
+        "[{}] removed from config, stopping",
+        program.display_name()
+    ));
+    program.removed = true;
+    program.waiting_dependencies = false;
+    program.backoff_until = None;
+    program.restart_after_stop = false;
+    if program.is_running() && program.stop_deadline.is_none() {
+        if let Err(e) = program.stop() {
+            logger::error(&format!("[{}] failed to stop: {e}", program.display_name()));
+        }
+    }
+}
+
+fn finish_backoff(program: &mut TMProgram) {
+    if program.backoff_until.is_some_and(|x| x > Instant::now()) {
+        return;
+    }
+    program.backoff_until = None;
+    if let Err(e) = program.launch() {
+        logger::error(&format!(
+            "[{}] failed to launch: {e}",
+            program.display_name()
+        ));
+        program.fatal = true;
+        event::emit(program.event(EventKind::ProcessFatal));
+    }
+}
+
+/// Run the probes of the program and return why it should be restarted, if it should
+fn check_probes(program: &mut TMProgram) -> Option<String> {
+    if !program.is_running() {
+        return None;
+    }
+    if let Some(probe) = &program.config.readiness {
+        let was_ready = program.readiness.success;
+        if program.readiness.poll(probe) && was_ready != program.readiness.success {
+            match program.readiness.success {
+                true => logger::info(&format!("[{}] ready", program.display_name())),
+                false => logger::info(&format!(
+                    "[{}] readiness probe failed",
+                    program.display_name()
+                )),
+            }
+        }
+    }
+    let health_time = Duration::from_secs(program.config.health_time.into());
+    if program.started_at.is_none_or(|x| x.elapsed() < health_time) {
+        return None;
+    }
+    let probe = program.config.liveness.as_ref()?;
+    if program.liveness.poll(probe) && program.liveness.is_failing(probe) {
+        return Some(format!(
+            "liveness probe failed {} times",
+            program.liveness.failures
+        ));
+    }
+    None
+}
+
+/// Sample the program usage and return why it should be restarted, if it should
+fn check_watchdog(program: &mut TMProgram) -> Option<String> {
+    let watchdog = &program.config.watchdog;
+    if watchdog.max_rss.is_none() && watchdog.max_cpu.is_none() {
+        return None;
+    }
+    let usage = program.usage().ok()?;
+    let now = Instant::now();
+    if let Some(max_rss) = watchdog.max_rss {
+        if usage.rss > max_rss {
+            return Some(format!("rss {} exceeds {max_rss} bytes", usage.rss));
+        }
+    }
+    let last_sample = program.last_sample.replace((now, usage.cpu_time));
+    let (Some(max_cpu), Some((last_time, last_cpu))) = (watchdog.max_cpu, last_sample) else {
+        return None;
+    };
+    let elapsed = now.duration_since(last_time).as_secs_f64();
+    let cpu = (usage.cpu_time.saturating_sub(last_cpu)).as_secs_f64() / elapsed * 100.0;
+    if cpu <= max_cpu as f64 {
+        program.cpu_over_since = None;
+        return None;
+    }
+    let since = *program.cpu_over_since.get_or_insert(now);
+    if now.duration_since(since) >= Duration::from_secs(watchdog.cpu_period.into()) {
+        return Some(format!(
+            "cpu {cpu:.0}% over {max_cpu}% for {} secs",
+            watchdog.cpu_period
+        ));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backoff(jitter: u32) -> TMBackoff {
+        TMBackoff {
+            initial_delay: 1000,
+            multiplier: 2,
+            max_delay: 10000,
+            jitter,
+        }
+    }
+
+    #[test]
+    fn backoff_grows_until_max() {
+        let delays: Vec<u128> = (1..=6)
+            .map(|x| backoff_delay(&backoff(0), x).as_millis())
+            .collect();
+        assert_eq!(delays, [1000, 2000, 4000, 8000, 10000, 10000]);
+    }
+
+    #[test]
+    fn backoff_does_not_overflow() {
+        assert_eq!(backoff_delay(&backoff(0), 200).as_millis(), 10000);
+    }
+
+    #[test]
+    fn backoff_jitter_stays_in_range() {
+        for attempt in 1..=5 {
+            let delay = backoff_delay(&backoff(10), attempt).as_millis();
+            let base = backoff_delay(&backoff(0), attempt).as_millis();
+            assert!(delay >= base * 9 / 10 && delay <= base * 11 / 10, "{delay}");
+        }
+    }
+
+    #[test]
+    fn backoff_jitter_is_capped() {
+        // over 100% the delay could go negative
+        let delay = backoff_delay(&backoff(500), 1).as_millis();
+        assert!(delay <= 2000);
+    }
+}
@@ -0,0 +1,82 @@
+use crate::program::TMProgram;
+
+/// What a command applies to
+#[derive(Debug, PartialEq, Eq)]
+pub enum Target {
+    /// Index of the instance in the `list` output
+    Index(u32),
+    /// Every instance of a program
+    Program(String),
+    /// One instance of a program, written `name:instance`
+    Instance(String, u32),
+    /// Every instance of every program of a group, written `group:name`
+    Group(String),
+    /// Every instance of every program
+    All,
+}
+
+impl From<&str> for Target {
+    fn from(value: &str) -> Self {
+        if value == "all" {
+            return Self::All;
+        }
+        if let Some(group) = value.strip_prefix("group:") {
+            return Self::Group(group.to_string());
+        }
+        if let Ok(idx) = value.parse() {
+            return Self::Index(idx);
+        }
+        match value.rsplit_once(':') {
+            Some((name, instance)) => match instance.parse() {
+                Ok(x) => Self::Instance(name.to_string(), x),
+                Err(_) => Self::Program(value.to_string()),
+            },
+            None => Self::Program(value.to_string()),
+        }
+    }
+}
+
+impl Target {
+    pub fn matches(&self, idx: usize, program: &TMProgram) -> bool {
+        match self {
+            Self::Index(x) => *x as usize == idx,
+            Self::Program(name) => *name == program.name,
+            Self::Instance(name, instance) => {
+                *name == program.name && *instance == program.instance
+            }
+            Self::Group(group) => program.groups.contains(group),
+            Self::All => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(Target::from("all"), Target::All);
+        assert_eq!(Target::from("3"), Target::Index(3));
+        assert_eq!(Target::from("web"), Target::Program("web".to_string()));
+        assert_eq!(
+            Target::from("web:2"),
+            Target::Instance("web".to_string(), 2)
+        );
+    }
+
+    #[test]
+    fn parse_group() {
+        assert_eq!(Target::from("group:web"), Target::Group("web".to_string()));
+        assert_eq!(Target::from("group:1"), Target::Group("1".to_string()));
+    }
+
+    #[test]
+    fn parse_colon_in_name() {
+        assert_eq!(
+            Target::from("a:b:1"),
+            Target::Instance("a:b".to_string(), 1)
+        );
+        assert_eq!(Target::from("a:b"), Target::Program("a:b".to_string()));
+    }
+}